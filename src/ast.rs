@@ -1,7 +1,9 @@
 // Copyright (c) Chris Gunn.
 // Licensed under the MIT license.
 
-use std::rc::Rc;
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use regex::Regex;
 
 #[derive(Clone, Debug)]
 pub struct FileTemplate {
@@ -50,13 +52,23 @@ pub struct ScalerTemplate {
 pub enum ScalarTemplateValue {
     String(Rc<String>),
     Expr(Statement),
+    // An empty node or an unquoted `~`.
+    Null,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Statement {
     Expr(Expr),
     If(StatementIf),
+    Elif(StatementIf),
+    Else,
     For(StatementFor),
+    Let(StatementLet),
+    With(StatementWith),
+    Match(StatementMatch),
+    Define(StatementDefine),
+    Call(StatementCall),
+    Include(StatementInclude),
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -68,6 +80,40 @@ pub struct StatementIf {
 pub struct StatementFor {
     pub bindings: Vec<ExprBinding>,
     pub iterable: Expr,
+    pub filter: Option<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatementLet {
+    pub binding: ExprBinding,
+    pub value: Expr,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatementWith {
+    pub value: Expr,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatementMatch {
+    pub value: Expr,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatementDefine {
+    pub name: Rc<String>,
+    pub params: Vec<ExprBinding>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatementCall {
+    pub name: Rc<String>,
+    pub args: Vec<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatementInclude {
+    pub path: Expr,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -80,8 +126,31 @@ pub enum Expr {
     False,
     Eq(ExprOpBinary),
     Ne(ExprOpBinary),
+    Lt(ExprOpBinary),
+    Le(ExprOpBinary),
+    Gt(ExprOpBinary),
+    Ge(ExprOpBinary),
     Integer(ExprInteger),
     Real(ExprReal),
+    And(ExprOpBinary),
+    Or(ExprOpBinary),
+    Not(ExprOpUnary),
+    Add(ExprOpBinary),
+    Sub(ExprOpBinary),
+    Mul(ExprOpBinary),
+    Div(ExprOpBinary),
+    Rem(ExprOpBinary),
+    Neg(ExprOpUnary),
+    Call(ExprCall),
+    Pipe(ExprPipe),
+    List(ExprList),
+    Map(ExprMap),
+    Null,
+    Coalesce(ExprOpBinary),
+    Conditional(ExprConditional),
+    In(ExprOpBinary),
+    NotIn(ExprOpBinary),
+    Match(ExprMatch),
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -104,6 +173,9 @@ pub enum ExprQuery {
     Root,
     Var(Rc<String>),
     Index(ExprIndex),
+    Slice(ExprSlice),
+    Wildcard(ExprWildcard),
+    Descent(ExprDescent),
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -112,15 +184,109 @@ pub struct ExprOpBinary {
     pub right: Box<Expr>,
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExprOpUnary {
+    pub value: Box<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExprConditional {
+    pub condition: Box<Expr>,
+    pub true_value: Box<Expr>,
+    pub false_value: Box<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExprCall {
+    pub name: Rc<String>,
+    pub args: Vec<Expr>,
+    pub regex_cache: RegexCache,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExprMatch {
+    pub value: Box<Expr>,
+    pub pattern: Box<Expr>,
+    pub regex_cache: RegexCache,
+}
+
+// Holds the last regex compiled by an expression node, so that the pattern isn't recompiled each time the expression
+// is evaluated (e.g. within a for loop).
+#[derive(Clone, Default)]
+pub struct RegexCache {
+    regex: Rc<RefCell<Option<Regex>>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExprPipe {
+    pub value: Box<Expr>,
+    pub filter: ExprCall,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExprList {
+    pub items: Vec<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExprMap {
+    pub entries: Vec<ExprMapEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExprMapEntry {
+    pub key: Expr,
+    pub value: Expr,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct ExprIndex {
     pub object: Box<ExprQuery>,
     pub index: Box<Expr>,
+    pub optional: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExprSlice {
+    pub object: Box<ExprQuery>,
+    pub start: Option<Box<Expr>>,
+    pub end: Option<Box<Expr>>,
+    pub optional: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExprWildcard {
+    pub object: Box<ExprQuery>,
+    pub optional: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExprDescent {
+    pub object: Box<ExprQuery>,
+    pub index: Box<Expr>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum ExprBinding {
     Var(Rc<String>),
+    Map(ExprBindingMap),
+    List(ExprBindingList),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExprBindingMap {
+    pub entries: Vec<ExprBindingMapEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExprBindingMapEntry {
+    pub key: Rc<String>,
+    pub binding: ExprBinding,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExprBindingList {
+    pub items: Vec<ExprBinding>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -137,6 +303,40 @@ pub struct SourceLocation {
     pub col: usize,
 }
 
+impl RegexCache {
+    pub fn get_or_compile(&self, pattern: &str) -> Result<Regex, regex::Error> {
+        let mut cached = self.regex.borrow_mut();
+        if let Some(regex) = cached.as_ref() {
+            if regex.as_str() == pattern {
+                return Ok(regex.clone());
+            }
+        }
+
+        let regex = Regex::new(pattern)?;
+        *cached = Some(regex.clone());
+        Ok(regex)
+    }
+}
+
+// The cache isn't part of the expression's value.
+impl PartialEq for RegexCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for SourceLocationSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.filename, self.start.line, self.start.col)
+    }
+}
+
+impl fmt::Debug for RegexCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RegexCache")
+    }
+}
+
 impl NodeTemplate {
     pub fn src_loc(&self) -> &SourceLocationSpan {
         match self {
@@ -145,4 +345,15 @@ impl NodeTemplate {
             | NodeTemplate::Scaler(ScalerTemplate { src_loc, .. }) => &src_loc,
         }
     }
+
+    // Returns the statement if the node consists of only a single template expression.
+    pub fn statement(&self) -> Option<&Statement> {
+        match self {
+            NodeTemplate::Scaler(ScalerTemplate { values, .. }) => match &values[..] {
+                [ScalarTemplateValue::Expr(statement)] => Some(statement),
+                _ => None,
+            },
+            NodeTemplate::Sequence(_) | NodeTemplate::Map(_) => None,
+        }
+    }
 }
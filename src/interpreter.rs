@@ -7,16 +7,17 @@ use std::collections::HashMap;
 
 use anyhow::Error;
 
-use crate::{ast::FileTemplate, cow_yaml::Yaml};
+use crate::{ast::FileTemplate, cow_yaml::Yaml, parser::Parser};
 
 use interpreter_run::InterpreterRun;
 
 pub fn interpret(
+    parser: &Parser,
     file_templ: &FileTemplate,
     config: Yaml,
     variables: HashMap<String, Yaml>,
 ) -> Result<Vec<Yaml>, Error> {
-    let mut interpreter_run = InterpreterRun::new(config, variables);
+    let mut interpreter_run = InterpreterRun::new(parser, config, variables);
     let file = interpreter_run.interpret_file(&file_templ)?;
     Ok(file)
 }
@@ -1,27 +1,60 @@
 // Copyright (c) Chris Gunn.
 // Licensed under the MIT license.
 
-use std::{collections::HashMap, rc::Rc};
+mod functions;
 
-use anyhow::{anyhow, Error};
+use std::{cmp::Ordering, collections::HashMap, fs, path::Path, rc::Rc};
+
+use anyhow::{anyhow, Context, Error};
 use hashlink::LinkedHashMap;
 
 use crate::{
     ast::{
-        Expr, ExprBinding, ExprIndex, ExprInteger, ExprOpBinary, ExprQuery, ExprReal, ExprString, FileTemplate,
-        MapTemplate, NodeTemplate, ScalarTemplateValue, ScalerTemplate, SequenceTemplate, SourceLocationSpan,
-        Statement, StatementFor, StatementIf,
+        Expr, ExprBinding, ExprCall, ExprConditional, ExprDescent, ExprIndex, ExprInteger, ExprList, ExprMap,
+        ExprMatch, ExprOpBinary, ExprOpUnary, ExprPipe, ExprQuery, ExprReal, ExprSlice, ExprString, ExprWildcard,
+        FileTemplate, MapTemplate, NodeTemplate, ScalarTemplateValue, ScalerTemplate, SequenceTemplate,
+        SourceLocationSpan, Statement, StatementCall, StatementDefine, StatementFor, StatementIf, StatementInclude,
+        StatementLet, StatementMatch, StatementWith,
     },
     cow_yaml::Yaml,
+    parser::Parser,
 };
 
-pub struct InterpreterRun {
-    config: Yaml,
+use functions::{compile_regex, find_function, new_range, Function, FunctionContext, Range};
+
+// The maximum depth of nested macro calls. This stops runaway recursion.
+const MAX_MACRO_DEPTH: usize = 32;
+
+pub struct InterpreterRun<'a> {
     scopes: Vec<Scope>,
+    // The macro calls that are currently being interpreted, outermost first.
+    macro_calls: Vec<MacroCall>,
+    // The files that are currently being interpreted, starting with the top-level template file.
+    include_chain: Vec<Rc<String>>,
+    // Used to parse included files.
+    parser: &'a Parser,
 }
 
+#[derive(Clone)]
 struct Scope {
     pub variables: HashMap<String, Yaml>,
+    pub macros: HashMap<String, Rc<Macro>>,
+    // The value that `.` refers to, if the scope rebinds it.
+    pub root: Option<Yaml>,
+}
+
+struct MacroCall {
+    pub name: Rc<String>,
+    pub src_loc: SourceLocationSpan,
+}
+
+struct Macro {
+    pub params: Vec<ExprBinding>,
+    pub body: NodeTemplate,
+    // The location of the macro's define statement.
+    pub src_loc: SourceLocationSpan,
+    // The variables, macros and root that are visible where the macro is defined.
+    pub scope: Scope,
 }
 
 struct Value {
@@ -36,6 +69,12 @@ enum ValueData {
     Drop,
     Nothing,
     For(ValueFor),
+    Let(ValueLet),
+    With(Yaml),
+    Match(Yaml),
+    Define(ValueDefine),
+    Call(ValueCall),
+    Include(Rc<String>),
 }
 
 enum ScalarValue {
@@ -43,11 +82,58 @@ enum ScalarValue {
     Drop,
     Yaml(Yaml),
     For(ValueFor),
+    Let(ValueLet),
+    With(Yaml),
+    Match(Yaml),
+    Define(ValueDefine),
+    Call(ValueCall),
+    Include(Rc<String>),
 }
 
 struct ValueFor {
     pub bindings: Vec<ExprBinding>,
-    pub iterable: Yaml,
+    pub iterable: Iterable,
+    pub filter: Option<Expr>,
+}
+
+enum Iterable {
+    List(Rc<Vec<Yaml>>),
+    Map(Rc<LinkedHashMap<Yaml, Yaml>>),
+    Range(Range),
+}
+
+struct ValueLet {
+    pub binding: ExprBinding,
+    pub value: Yaml,
+}
+
+struct ValueDefine {
+    pub name: Rc<String>,
+    pub params: Vec<ExprBinding>,
+}
+
+struct ValueCall {
+    pub name: Rc<String>,
+    pub args: Vec<Yaml>,
+}
+
+#[derive(Clone, Copy)]
+enum ArithmeticOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+enum QueryResult {
+    Found(Yaml),
+    // The query's path doesn't exist. Holds the error to report if the caller requires the value.
+    Missing(Error),
+    // An optional segment of the query couldn't be resolved. So, the whole query evaluates to null.
+    Skipped,
+    // The query has a wildcard or a recursive descent. So, it matches a list of values.
+    Multi(Vec<Yaml>),
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -57,21 +143,23 @@ enum ExprValue {
     Yaml(Yaml),
 }
 
-macro_rules! errwithloc {
-    ($loc:expr, $fmt:expr $(, $($arg:tt)*)?) => {
-        anyhow!(concat!("{}:{}:{} ", $fmt), $loc.filename, $loc.start.line, $loc.start.col, $($($arg)*)?)
-    };
-}
-
-impl InterpreterRun {
-    pub fn new(config: Yaml, variables: HashMap<String, Yaml>) -> InterpreterRun {
+impl InterpreterRun<'_> {
+    pub fn new(parser: &Parser, config: Yaml, variables: HashMap<String, Yaml>) -> InterpreterRun<'_> {
         InterpreterRun {
-            config,
-            scopes: vec![Scope { variables }],
+            scopes: vec![Scope {
+                variables,
+                macros: HashMap::new(),
+                root: Some(config),
+            }],
+            macro_calls: Vec::new(),
+            include_chain: Vec::new(),
+            parser,
         }
     }
 
     pub fn interpret_file(&mut self, file_templ: &FileTemplate) -> Result<Vec<Yaml>, Error> {
+        self.include_chain.push(file_templ.src_loc.filename.clone());
+
         let mut docs = Vec::new();
         for doc_templ in &file_templ.docs {
             let value = self.interpret_node(&doc_templ.node)?;
@@ -82,25 +170,42 @@ impl InterpreterRun {
                     docs.push(value);
                 }
                 ValueData::Nothing => {}
-                ValueData::Inline | ValueData::Drop | ValueData::For(..) => unreachable!(),
+                ValueData::Inline
+                | ValueData::Drop
+                | ValueData::For(..)
+                | ValueData::Let(..)
+                | ValueData::With(..)
+                | ValueData::Match(..)
+                | ValueData::Define(..)
+                | ValueData::Call(..)
+                | ValueData::Include(..) => {
+                    unreachable!()
+                }
             };
         }
 
+        self.include_chain.pop();
         Ok(docs)
     }
 
     fn interpret_node(&mut self, node_templ: &NodeTemplate) -> Result<Value, Error> {
         match node_templ {
             NodeTemplate::Sequence(seq_templ) => self.interpret_seq(seq_templ),
-            NodeTemplate::Map(map_templ) => self.interpret_map(map_templ),
+            NodeTemplate::Map(map_templ) => self.interpret_map(map_templ, &mut false),
             NodeTemplate::Scaler(scalar_templ) => self.interpret_scalar(scalar_templ),
         }
     }
 
     fn interpret_seq(&mut self, seq_templ: &SequenceTemplate) -> Result<Value, Error> {
+        // An if chain can span multiple list items, where each item is a single entry map.
+        let mut branch_taken = false;
+
         let mut values = Vec::new();
         for value_templ in &seq_templ.values {
-            let value = self.interpret_node(value_templ)?;
+            let value = match value_templ {
+                NodeTemplate::Map(map_templ) => self.interpret_map(map_templ, &mut branch_taken)?,
+                _ => self.interpret_node(value_templ)?,
+            };
             let value = Self::expect_value(value)?;
             match value.data {
                 ValueData::Yaml(yaml) => {
@@ -122,7 +227,17 @@ impl InterpreterRun {
                 }
                 ValueData::Nothing => {}
                 // Checked by expect_value()
-                ValueData::Inline | ValueData::Drop | ValueData::For(_) => unreachable!(),
+                ValueData::Inline
+                | ValueData::Drop
+                | ValueData::For(_)
+                | ValueData::Let(_)
+                | ValueData::With(_)
+                | ValueData::Match(_)
+                | ValueData::Define(_)
+                | ValueData::Call(_)
+                | ValueData::Include(_) => {
+                    unreachable!()
+                }
             }
         }
 
@@ -135,14 +250,39 @@ impl InterpreterRun {
         Ok(value)
     }
 
-    fn interpret_map(&mut self, map_templ: &MapTemplate) -> Result<Value, Error> {
+    // The `branch_taken` parameter tracks whether a branch of the current if chain has already been taken.
+    fn interpret_map(&mut self, map_templ: &MapTemplate, branch_taken: &mut bool) -> Result<Value, Error> {
+        // Variables bound by let statements are only visible within the map.
+        self.push_scope();
+        let res = self.interpret_map_entries(map_templ, branch_taken);
+        self.pop_scope();
+        res
+    }
+
+    fn interpret_map_entries(&mut self, map_templ: &MapTemplate, branch_taken: &mut bool) -> Result<Value, Error> {
         // If there is only a single item in a map and that item is a template expression,
         // then we allow inline and drop commands to apply to the parent value.
         let one_item_map = map_templ.entries.len() == 1;
 
         let mut entries = LinkedHashMap::new();
         for entry_templ in &map_templ.entries {
-            let key = self.interpret_node(&entry_templ.key)?;
+            let branch = entry_templ.key.statement();
+            let key = match branch {
+                // Only one branch of an if chain is taken. So, drop the remaining branches.
+                Some(Statement::Elif(_)) | Some(Statement::Else) if *branch_taken => Value {
+                    src_loc: entry_templ.key.src_loc().clone(),
+                    data: ValueData::Drop,
+                },
+                _ => self.interpret_node(&entry_templ.key)?,
+            };
+
+            match branch {
+                Some(Statement::If(_)) => *branch_taken = matches!(key.data, ValueData::Inline),
+                Some(Statement::Elif(_)) | Some(Statement::Else) => {
+                    *branch_taken |= matches!(key.data, ValueData::Inline)
+                }
+                _ => {}
+            }
             match key.data {
                 ValueData::Yaml(key) => {
                     let entry_value = self.interpret_node(&entry_templ.value)?;
@@ -152,14 +292,76 @@ impl InterpreterRun {
                         // In YAML, a key without a value is given a default value of null.
                         ValueData::Nothing => Yaml::Null,
                         // Checked by expect_value().
-                        ValueData::Inline | ValueData::Drop | ValueData::For(_) => unreachable!(),
+                        ValueData::Inline
+                        | ValueData::Drop
+                        | ValueData::For(_)
+                        | ValueData::Let(_)
+                        | ValueData::With(_)
+                        | ValueData::Match(_)
+                        | ValueData::Define(_)
+                        | ValueData::Call(_)
+                        | ValueData::Include(_) => unreachable!(),
                     };
                     entries.insert(key, entry_value);
                 }
-                key_data @ ValueData::Inline | key_data @ ValueData::For(_) => {
+                key_data @ (ValueData::Inline
+                | ValueData::For(_)
+                | ValueData::Let(_)
+                | ValueData::With(_)
+                | ValueData::Match(_)
+                | ValueData::Define(_)
+                | ValueData::Call(_)
+                | ValueData::Include(_)) => {
                     let entry_value = match key_data {
                         ValueData::Inline => self.interpret_node(&entry_templ.value)?,
                         ValueData::For(value_for) => self.run_for_loop(&key.src_loc, value_for, &entry_templ.value)?,
+                        ValueData::Let(value_let) => {
+                            // The binding is visible to the entry's value and to the entries that follow it.
+                            self.add_binding(&value_let.binding, &value_let.value, None, &key.src_loc)?;
+                            let entry_value = self.interpret_node(&entry_templ.value)?;
+                            // A let statement without a value doesn't add anything to the map.
+                            Self::empty_to_nothing(entry_value)
+                        }
+                        ValueData::With(root) => {
+                            self.push_root_scope(root);
+                            let entry_value = self.interpret_node(&entry_templ.value);
+                            self.pop_scope();
+                            entry_value?
+                        }
+                        ValueData::Match(value) => self.run_match(&key.src_loc, &value, &entry_templ.value)?,
+                        ValueData::Define(define) => {
+                            // The macro is visible to the entry's value and to the entries that follow it.
+                            let macro_def = Macro {
+                                params: define.params,
+                                body: entry_templ.value.clone(),
+                                src_loc: key.src_loc.clone(),
+                                scope: self.capture_scope(),
+                            };
+                            self.add_macro(&define.name, macro_def);
+                            Value {
+                                src_loc: key.src_loc.clone(),
+                                data: ValueData::Nothing,
+                            }
+                        }
+                        ValueData::Call(call) => {
+                            let entry_value = self.interpret_node(&entry_templ.value)?;
+                            let entry_value = Self::empty_to_nothing(entry_value);
+                            if !matches!(entry_value.data, ValueData::Nothing) {
+                                return Err(errwithloc!(entry_value.src_loc, "call statement cannot have a value"));
+                            }
+                            self.run_macro(&key.src_loc, call)?
+                        }
+                        ValueData::Include(path) => {
+                            let entry_value = self.interpret_node(&entry_templ.value)?;
+                            let entry_value = Self::empty_to_nothing(entry_value);
+                            if !matches!(entry_value.data, ValueData::Nothing) {
+                                return Err(errwithloc!(
+                                    entry_value.src_loc,
+                                    "include statement cannot have a value"
+                                ));
+                            }
+                            self.run_include(&key.src_loc, &path)?
+                        }
                         _ => unreachable!(),
                     };
 
@@ -196,7 +398,15 @@ impl InterpreterRun {
                         },
                         ValueData::Nothing => {}
                         // Checked by expect_value().
-                        ValueData::Inline | ValueData::Drop | ValueData::For(_) => unreachable!(),
+                        ValueData::Inline
+                        | ValueData::Drop
+                        | ValueData::For(_)
+                        | ValueData::Let(_)
+                        | ValueData::With(_)
+                        | ValueData::Match(_)
+                        | ValueData::Define(_)
+                        | ValueData::Call(_)
+                        | ValueData::Include(_) => unreachable!(),
                     }
                 }
                 ValueData::Drop => {
@@ -225,6 +435,218 @@ impl InterpreterRun {
         Ok(value)
     }
 
+    // Interprets a macro's body, with the macro's params bound to the call's args.
+    fn run_macro(&mut self, call_src_loc: &SourceLocationSpan, call: ValueCall) -> Result<Value, Error> {
+        let Some(macro_def) = self.find_macro(&call.name) else {
+            return Err(errwithloc!(call_src_loc, "unknown macro '{}'", call.name));
+        };
+
+        if call.args.len() != macro_def.params.len() {
+            return Err(errwithloc!(
+                call_src_loc,
+                "macro '{}' expects {} argument(s), found {}",
+                call.name,
+                macro_def.params.len(),
+                call.args.len()
+            ));
+        }
+
+        if self.macro_calls.len() >= MAX_MACRO_DEPTH {
+            return Err(errwithloc!(
+                call_src_loc,
+                "macro '{}' exceeded the maximum call depth of {}",
+                call.name,
+                MAX_MACRO_DEPTH
+            ));
+        }
+
+        // The body only sees what is visible where the macro is defined, not the caller's variables.
+        // The macro is added to its own scope, so that it can call itself.
+        let mut macro_scope = macro_def.scope.clone();
+        macro_scope.macros.insert(call.name.to_string(), macro_def.clone());
+        let caller_scopes = std::mem::replace(&mut self.scopes, vec![macro_scope]);
+
+        let macro_call = MacroCall {
+            name: call.name.clone(),
+            src_loc: call_src_loc.clone(),
+        };
+
+        // A recursive macro's errors pass through the same call site many times. So, only the outermost call from
+        // each call site is reported.
+        let repeated_call = self
+            .macro_calls
+            .iter()
+            .any(|outer_call| outer_call.name == macro_call.name && outer_call.src_loc == macro_call.src_loc);
+
+        self.macro_calls.push(macro_call);
+        self.push_scope();
+        let res = self.interpret_macro_body(&macro_def, &call.args, call_src_loc);
+        self.macro_calls.pop();
+        self.scopes = caller_scopes;
+
+        match repeated_call {
+            true => res,
+            false => res.with_context(|| {
+                format!(
+                    "{} in macro '{}' (defined at {})",
+                    call_src_loc, call.name, macro_def.src_loc
+                )
+            }),
+        }
+    }
+
+    fn interpret_macro_body(
+        &mut self,
+        macro_def: &Macro,
+        args: &[Yaml],
+        call_src_loc: &SourceLocationSpan,
+    ) -> Result<Value, Error> {
+        for (param, arg) in macro_def.params.iter().zip(args) {
+            self.add_binding(param, arg, None, call_src_loc)?;
+        }
+
+        let value = self.interpret_node(&macro_def.body)?;
+        Ok(Self::empty_to_nothing(value))
+    }
+
+    // Interprets another template file, in the current scope.
+    // The path is relative to the file that contains the include statement.
+    fn run_include(&mut self, include_src_loc: &SourceLocationSpan, path: &str) -> Result<Value, Error> {
+        let including_dir = Path::new(include_src_loc.filename.as_str())
+            .parent()
+            .unwrap_or(Path::new(""));
+        let filename = Rc::new(including_dir.join(path).to_string_lossy().into_owned());
+
+        if self
+            .include_chain
+            .iter()
+            .any(|included| Self::is_same_file(included, &filename))
+        {
+            let chain: Vec<&str> = self.include_chain.iter().map(|included| included.as_str()).collect();
+            return Err(errwithloc!(
+                include_src_loc,
+                "include cycle detected: {} -> {}",
+                chain.join(" -> "),
+                filename
+            ));
+        }
+
+        let template = fs::read_to_string(filename.as_str())
+            .map_err(|err| errwithloc!(include_src_loc, "failed to read included file '{}': {}", filename, err))?;
+
+        let context = || format!("{} in included file '{}'", include_src_loc, filename);
+
+        let file_templ = self.parser.parse(&filename, &template).with_context(context)?;
+        let [doc_templ] = file_templ.docs.as_slice() else {
+            return Err(errwithloc!(
+                include_src_loc,
+                "included file '{}' must have exactly 1 document, found {}",
+                filename,
+                file_templ.docs.len()
+            ));
+        };
+
+        self.include_chain.push(filename.clone());
+        let res = self.interpret_included_node(&doc_templ.node);
+        self.include_chain.pop();
+
+        res.with_context(context)
+    }
+
+    fn interpret_included_node(&mut self, node_templ: &NodeTemplate) -> Result<Value, Error> {
+        let value = match node_templ {
+            // The top-level map of the included file shares the current scope. So, its let and define statements are
+            // visible to the entries that follow the include statement.
+            NodeTemplate::Map(map_templ) => self.interpret_map_entries(map_templ, &mut false)?,
+            node_templ => self.interpret_node(node_templ)?,
+        };
+        Ok(Self::empty_to_nothing(value))
+    }
+
+    fn is_same_file(left: &str, right: &str) -> bool {
+        match (fs::canonicalize(left), fs::canonicalize(right)) {
+            (Ok(left), Ok(right)) => left == right,
+            _ => left == right,
+        }
+    }
+
+    // Empty map values are treated as having no value.
+    fn empty_to_nothing(value: Value) -> Value {
+        match value.data {
+            ValueData::Yaml(Yaml::Null) => Value {
+                src_loc: value.src_loc,
+                data: ValueData::Nothing,
+            },
+            _ => value,
+        }
+    }
+
+    // Interprets the child node of the case that matches the value.
+    // The case patterns are the keys of the match statement's child map. The `_` case matches any value.
+    fn run_match(
+        &mut self,
+        key_src_loc: &SourceLocationSpan,
+        value: &Yaml,
+        cases_templ: &NodeTemplate,
+    ) -> Result<Value, Error> {
+        let NodeTemplate::Map(cases_templ) = cases_templ else {
+            return Err(errwithloc!(key_src_loc, "match statement requires a map of cases"));
+        };
+
+        // Case keys are always strings. So, compare scalars using their string form.
+        let value_string = Self::match_value_string(value);
+
+        let mut matched_case = None;
+        let mut default_case = None;
+        for case_templ in &cases_templ.entries {
+            let pattern = Self::expect_case_pattern(&case_templ.key)?;
+            if pattern == "_" {
+                default_case.get_or_insert(case_templ);
+            } else if matched_case.is_none() && value_string.as_deref() == Some(pattern) {
+                matched_case = Some(case_templ);
+            }
+        }
+
+        let Some(case_templ) = matched_case.or(default_case) else {
+            return Err(match value_string {
+                Some(string) => errwithloc!(
+                    key_src_loc,
+                    "match statement has no case for value '{}' and no default case",
+                    string
+                ),
+                _ => errwithloc!(
+                    key_src_loc,
+                    "match statement has no case for value of type {} and no default case",
+                    Self::yaml_type_name(value)
+                ),
+            });
+        };
+
+        let case_value = self.interpret_node(&case_templ.value)?;
+        Ok(Self::empty_to_nothing(case_value))
+    }
+
+    fn match_value_string(value: &Yaml) -> Option<String> {
+        match value {
+            Yaml::String(value) | Yaml::Real(value) => Some(value.as_ref().clone()),
+            Yaml::Integer(value) => Some(value.to_string()),
+            Yaml::Boolean(value) => Some(value.to_string()),
+            Yaml::Null => Some("null".to_string()),
+            Yaml::Array(_) | Yaml::Hash(_) => None,
+        }
+    }
+
+    fn expect_case_pattern(case_key: &NodeTemplate) -> Result<&str, Error> {
+        let error = || errwithloc!(case_key.src_loc(), "match case must be a string literal or '_'");
+        let NodeTemplate::Scaler(scalar_templ) = case_key else {
+            return Err(error());
+        };
+        match scalar_templ.values.as_slice() {
+            [ScalarTemplateValue::String(string)] => Ok(string.as_str()),
+            _ => Err(error()),
+        }
+    }
+
     fn run_for_loop(
         &mut self,
         key_src_loc: &SourceLocationSpan,
@@ -269,51 +691,75 @@ impl InterpreterRun {
             Ok(())
         };
 
-        match value_for.iterable {
-            Yaml::Array(list) => {
-                if value_for.bindings.len() != 1 {
-                    return Err(errwithloc!(
-                        key_src_loc,
-                        "for loop over list requires 1 binding item, found {}",
-                        value_for.bindings.len()
-                    ));
-                }
-
-                for item in list.as_ref() {
-                    self.push_scope();
-                    self.add_binding(&value_for.bindings[0], item);
-
-                    let item = self.interpret_node(item_templ)?;
+        let expected_bindings = match value_for.iterable {
+            // List items that are tuples (e.g. from zip() or product()) can be split across multiple bindings.
+            Iterable::List(_) => None,
+            Iterable::Map(_) => Some(("map", 2)),
+            Iterable::Range(_) => Some(("range", 1)),
+        };
+        if let Some((iterable_name, expected_len)) = expected_bindings {
+            if value_for.bindings.len() != expected_len {
+                return Err(errwithloc!(
+                    key_src_loc,
+                    "for loop over {} requires {} binding item(s), found {}",
+                    iterable_name,
+                    expected_len,
+                    value_for.bindings.len()
+                ));
+            }
+        }
 
-                    self.pop_scope();
+        // The values of the loop's bindings, for each iteration.
+        let mut length = match &value_for.iterable {
+            Iterable::List(list) => list.len(),
+            Iterable::Map(map) => map.len(),
+            Iterable::Range(range) => range.len(),
+        };
+        let bindings_len = value_for.bindings.len();
+        let mut iterations: Box<dyn Iterator<Item = Result<Vec<Yaml>, Error>>> = match &value_for.iterable {
+            Iterable::List(list) if bindings_len == 1 => Box::new(list.iter().map(|item| Ok(vec![item.clone()]))),
+            Iterable::List(list) => Box::new(
+                list.iter()
+                    .enumerate()
+                    .map(|(index, item)| Self::expect_tuple(item, bindings_len, index, key_src_loc)),
+            ),
+            Iterable::Map(map) => Box::new(map.iter().map(|(key, value)| Ok(vec![key.clone(), value.clone()]))),
+            Iterable::Range(range) => Box::new(range.iter().map(|item| Ok(vec![Yaml::Integer(item)]))),
+        };
 
-                    add_item(item)?;
+        // Skip the items that don't pass the filter. This is done before running the loop's body, so that `$loop`
+        // only counts the items that are kept.
+        if let Some(filter) = &value_for.filter {
+            let mut kept = Vec::new();
+            for (index, values) in iterations.enumerate() {
+                let values = values?;
+                self.push_scope();
+                let keep = self
+                    .add_bindings(&value_for.bindings, &values, index, key_src_loc)
+                    .and_then(|_| self.interpret_expr(filter, key_src_loc));
+                self.pop_scope();
+
+                if Self::expect_implicit_bool(keep?, key_src_loc)? {
+                    kept.push(values);
                 }
             }
-            Yaml::Hash(map) => {
-                if value_for.bindings.len() != 2 {
-                    return Err(errwithloc!(
-                        key_src_loc,
-                        "for loop over map requires 2 binding items, found {}",
-                        value_for.bindings.len()
-                    ));
-                }
+            length = kept.len();
+            iterations = Box::new(kept.into_iter().map(Ok));
+        }
 
-                for (key, value) in map.as_ref() {
-                    self.push_scope();
+        for (index, values) in iterations.enumerate() {
+            let values = values?;
+            self.push_scope();
 
-                    self.add_binding(&value_for.bindings[0], key);
-                    self.add_binding(&value_for.bindings[1], value);
+            self.add_loop_variable(index, length);
 
-                    let item = self.interpret_node(item_templ)?;
+            let item = self
+                .add_bindings(&value_for.bindings, &values, index, key_src_loc)
+                .and_then(|_| self.interpret_node(item_templ));
 
-                    self.pop_scope();
+            self.pop_scope();
 
-                    add_item(item)?;
-                }
-            }
-            // Check by expect_iterable() in interpret_for().
-            _ => unreachable!(),
+            add_item(item?)?;
         }
 
         let mut data = ValueData::Nothing;
@@ -346,6 +792,9 @@ impl InterpreterRun {
                     let value = self.interpret_statement(stmt, &scalar_templ.src_loc)?;
                     values.push(value);
                 }
+                ScalarTemplateValue::Null => {
+                    values.push(ScalarValue::Yaml(Yaml::Null));
+                }
             }
         }
 
@@ -356,6 +805,12 @@ impl InterpreterRun {
                 ScalarValue::Drop => ValueData::Drop,
                 ScalarValue::Yaml(yaml) => ValueData::Yaml(yaml),
                 ScalarValue::For(value_for) => ValueData::For(value_for),
+                ScalarValue::Let(value_let) => ValueData::Let(value_let),
+                ScalarValue::With(root) => ValueData::With(root),
+                ScalarValue::Match(value) => ValueData::Match(value),
+                ScalarValue::Define(define) => ValueData::Define(define),
+                ScalarValue::Call(call) => ValueData::Call(call),
+                ScalarValue::Include(path) => ValueData::Include(path),
             };
             let value = Value {
                 src_loc: scalar_templ.src_loc.clone(),
@@ -397,6 +852,42 @@ impl InterpreterRun {
                         "expression value 'for' cannot be a substring"
                     ))
                 }
+                ScalarValue::Let(_) => {
+                    return Err(errwithloc!(
+                        scalar_templ.src_loc,
+                        "expression value 'let' cannot be a substring"
+                    ))
+                }
+                ScalarValue::With(_) => {
+                    return Err(errwithloc!(
+                        scalar_templ.src_loc,
+                        "expression value 'with' cannot be a substring"
+                    ))
+                }
+                ScalarValue::Match(_) => {
+                    return Err(errwithloc!(
+                        scalar_templ.src_loc,
+                        "expression value 'match' cannot be a substring"
+                    ))
+                }
+                ScalarValue::Define(_) => {
+                    return Err(errwithloc!(
+                        scalar_templ.src_loc,
+                        "expression value 'define' cannot be a substring"
+                    ))
+                }
+                ScalarValue::Call(_) => {
+                    return Err(errwithloc!(
+                        scalar_templ.src_loc,
+                        "expression value 'call' cannot be a substring"
+                    ))
+                }
+                ScalarValue::Include(_) => {
+                    return Err(errwithloc!(
+                        scalar_templ.src_loc,
+                        "expression value 'include' cannot be a substring"
+                    ))
+                }
             }
         }
         let data = ValueData::Yaml(Yaml::String(Rc::new(string)));
@@ -418,9 +909,95 @@ impl InterpreterRun {
                 };
                 Ok(scalar_value)
             }
-            Statement::If(if_stmt) => self.interpret_if(if_stmt, src_loc),
+            Statement::If(if_stmt) | Statement::Elif(if_stmt) => self.interpret_if(if_stmt, src_loc),
+            Statement::Else => Ok(ScalarValue::Inline),
             Statement::For(for_stmt) => self.interpret_for(for_stmt, src_loc),
+            Statement::Let(let_stmt) => self.interpret_let(let_stmt, src_loc),
+            Statement::With(with_stmt) => self.interpret_with(with_stmt, src_loc),
+            Statement::Match(match_stmt) => self.interpret_match_statement(match_stmt, src_loc),
+            Statement::Define(define_stmt) => Ok(Self::interpret_define(define_stmt)),
+            Statement::Call(call_stmt) => self.interpret_call_statement(call_stmt, src_loc),
+            Statement::Include(include_stmt) => self.interpret_include(include_stmt, src_loc),
+        }
+    }
+
+    fn interpret_include(
+        &mut self,
+        include_stmt: &StatementInclude,
+        src_loc: &SourceLocationSpan,
+    ) -> Result<ScalarValue, Error> {
+        let path = self.interpret_expr(&include_stmt.path, src_loc)?;
+        match path {
+            ExprValue::Yaml(Yaml::String(path)) => Ok(ScalarValue::Include(path)),
+            _ => Err(errwithloc!(
+                src_loc,
+                "include path must be a string, found value of type {}",
+                Self::exp_value_type_name(&path)
+            )),
+        }
+    }
+
+    fn interpret_define(define_stmt: &StatementDefine) -> ScalarValue {
+        let define = ValueDefine {
+            name: define_stmt.name.clone(),
+            params: define_stmt.params.clone(),
+        };
+        ScalarValue::Define(define)
+    }
+
+    fn interpret_call_statement(
+        &mut self,
+        call_stmt: &StatementCall,
+        src_loc: &SourceLocationSpan,
+    ) -> Result<ScalarValue, Error> {
+        let mut args = Vec::new();
+        for arg in &call_stmt.args {
+            let arg = self.interpret_expr(arg, src_loc)?;
+            let arg = Self::expect_yaml(arg, "a macro argument", src_loc)?;
+            args.push(arg);
+        }
+
+        let call = ValueCall {
+            name: call_stmt.name.clone(),
+            args,
+        };
+        Ok(ScalarValue::Call(call))
+    }
+
+    fn interpret_match_statement(
+        &mut self,
+        match_stmt: &StatementMatch,
+        src_loc: &SourceLocationSpan,
+    ) -> Result<ScalarValue, Error> {
+        let value = self.interpret_expr(&match_stmt.value, src_loc)?;
+        let value = Self::expect_yaml(value, "the value of a match statement", src_loc)?;
+        Ok(ScalarValue::Match(value))
+    }
+
+    fn interpret_with(
+        &mut self,
+        with_stmt: &StatementWith,
+        src_loc: &SourceLocationSpan,
+    ) -> Result<ScalarValue, Error> {
+        let value = self.interpret_expr(&with_stmt.value, src_loc)?;
+
+        // Like an if statement, the child is dropped if the value is null or false.
+        if !Self::expect_implicit_bool(value.clone(), src_loc)? {
+            return Ok(ScalarValue::Drop);
         }
+
+        let root = Self::expect_yaml(value, "the root of a with statement", src_loc)?;
+        Ok(ScalarValue::With(root))
+    }
+
+    fn interpret_let(&mut self, let_stmt: &StatementLet, src_loc: &SourceLocationSpan) -> Result<ScalarValue, Error> {
+        let value = self.interpret_expr(&let_stmt.value, src_loc)?;
+        let value = Self::expect_yaml(value, "a variable's value", src_loc)?;
+        let value_let = ValueLet {
+            binding: let_stmt.binding.clone(),
+            value,
+        };
+        Ok(ScalarValue::Let(value_let))
     }
 
     fn interpret_if(&mut self, if_stmt: &StatementIf, src_loc: &SourceLocationSpan) -> Result<ScalarValue, Error> {
@@ -433,11 +1010,22 @@ impl InterpreterRun {
     }
 
     fn interpret_for(&mut self, for_stmt: &StatementFor, src_loc: &SourceLocationSpan) -> Result<ScalarValue, Error> {
-        let iterable = self.interpret_expr(&for_stmt.iterable, src_loc)?;
-        let iterable = Self::expect_iterable(iterable, src_loc)?;
+        let iterable = match &for_stmt.iterable {
+            // Ranges are iterated lazily, instead of creating the full list of integers.
+            Expr::Call(call) if call.name.as_str() == "range" => {
+                let args = self.interpret_args(&call.args, src_loc)?;
+                Self::check_arg_count(find_function("range").unwrap(), &args, src_loc)?;
+                Iterable::Range(new_range(&args, src_loc)?)
+            }
+            iterable => {
+                let iterable = self.interpret_expr(iterable, src_loc)?;
+                Self::expect_iterable(iterable, src_loc)?
+            }
+        };
         let value_for = ValueFor {
             bindings: for_stmt.bindings.clone(),
             iterable,
+            filter: for_stmt.filter.clone(),
         };
         let scalar_value = ScalarValue::For(value_for);
         Ok(scalar_value)
@@ -453,8 +1041,31 @@ impl InterpreterRun {
             Expr::False => Ok(ExprValue::Yaml(Yaml::Boolean(false))),
             Expr::Eq(op) => self.interpret_eq(op, src_loc),
             Expr::Ne(op) => self.interpret_ne(op, src_loc),
+            Expr::Lt(op) => self.interpret_compare(op, src_loc, Ordering::is_lt),
+            Expr::Le(op) => self.interpret_compare(op, src_loc, Ordering::is_le),
+            Expr::Gt(op) => self.interpret_compare(op, src_loc, Ordering::is_gt),
+            Expr::Ge(op) => self.interpret_compare(op, src_loc, Ordering::is_ge),
             Expr::Integer(integer) => self.interpret_integer(integer),
             Expr::Real(real) => self.interpret_real(real),
+            Expr::And(op) => self.interpret_and(op, src_loc),
+            Expr::Or(op) => self.interpret_or(op, src_loc),
+            Expr::Not(op) => self.interpret_not(op, src_loc),
+            Expr::Add(op) => self.interpret_arithmetic(op, src_loc, ArithmeticOp::Add),
+            Expr::Sub(op) => self.interpret_arithmetic(op, src_loc, ArithmeticOp::Sub),
+            Expr::Mul(op) => self.interpret_arithmetic(op, src_loc, ArithmeticOp::Mul),
+            Expr::Div(op) => self.interpret_arithmetic(op, src_loc, ArithmeticOp::Div),
+            Expr::Rem(op) => self.interpret_arithmetic(op, src_loc, ArithmeticOp::Rem),
+            Expr::Neg(op) => self.interpret_neg(op, src_loc),
+            Expr::Call(call) => self.interpret_call(call, src_loc),
+            Expr::Pipe(pipe) => self.interpret_pipe(pipe, src_loc),
+            Expr::List(list) => self.interpret_list(list, src_loc),
+            Expr::Map(map) => self.interpret_map_literal(map, src_loc),
+            Expr::Null => Ok(ExprValue::Yaml(Yaml::Null)),
+            Expr::Coalesce(op) => self.interpret_coalesce(op, src_loc),
+            Expr::Conditional(conditional) => self.interpret_conditional(conditional, src_loc),
+            Expr::In(op) => self.interpret_in(op, src_loc),
+            Expr::NotIn(op) => self.interpret_not_in(op, src_loc),
+            Expr::Match(expr_match) => self.interpret_match(expr_match, src_loc),
         }
     }
 
@@ -476,26 +1087,252 @@ impl InterpreterRun {
     }
 
     fn query(&mut self, query: &ExprQuery, src_loc: &SourceLocationSpan) -> Result<Yaml, Error> {
+        match self.try_query(query, src_loc)? {
+            QueryResult::Found(value) => Ok(value),
+            QueryResult::Missing(err) => Err(err),
+            QueryResult::Skipped => Ok(Yaml::Null),
+            QueryResult::Multi(values) => Ok(Yaml::Array(Rc::new(values))),
+        }
+    }
+
+    // Like query(), except that a query whose path doesn't exist is reported as QueryResult::Missing instead of
+    // as an error. Unknown variables are still errors.
+    fn try_query(&mut self, query: &ExprQuery, src_loc: &SourceLocationSpan) -> Result<QueryResult, Error> {
         match query {
-            ExprQuery::Root => Ok(self.config.clone()),
+            ExprQuery::Root => Ok(QueryResult::Found(self.query_root())),
             ExprQuery::Var(name) => self.query_var(name, src_loc),
             ExprQuery::Index(objectindex) => self.query_index(objectindex, src_loc),
+            ExprQuery::Slice(slice) => self.query_slice(slice, src_loc),
+            ExprQuery::Wildcard(wildcard) => self.query_wildcard(wildcard, src_loc),
+            ExprQuery::Descent(descent) => self.query_descent(descent, src_loc),
         }
     }
 
-    fn query_var(&mut self, name: &str, src_loc: &SourceLocationSpan) -> Result<Yaml, Error> {
+    fn query_root(&self) -> Yaml {
+        let root = self.scopes.iter().rev().find_map(|scope| scope.root.as_ref());
+        // The first scope always has a root.
+        root.unwrap().clone()
+    }
+
+    // An unknown variable is always an error, even in an optional query or on the left of `??`. Otherwise, a
+    // misspelt variable name would be silently hidden.
+    fn query_var(&mut self, name: &str, src_loc: &SourceLocationSpan) -> Result<QueryResult, Error> {
         for scope in self.scopes.iter().rev() {
             if let Some(value) = scope.variables.get(name) {
-                return Ok(value.clone());
+                return Ok(QueryResult::Found(value.clone()));
             }
         }
 
         Err(errwithloc!(src_loc, "cannot find variable '{}'", name))
     }
 
-    fn query_index(&mut self, objectindex: &ExprIndex, src_loc: &SourceLocationSpan) -> Result<Yaml, Error> {
+    fn query_index(&mut self, objectindex: &ExprIndex, src_loc: &SourceLocationSpan) -> Result<QueryResult, Error> {
         let index = self.interpret_expr(&objectindex.index, src_loc)?;
-        let object = self.query(&objectindex.object, src_loc)?;
+        let object = match self.try_query(&objectindex.object, src_loc)? {
+            QueryResult::Found(object) => object,
+            QueryResult::Multi(objects) => {
+                return Self::map_multi(objects, objectindex.optional, |object| {
+                    Self::index_value(object, index.clone(), src_loc)
+                })
+            }
+            res @ (QueryResult::Missing(_) | QueryResult::Skipped) => return Ok(res),
+        };
+
+        let res = Self::index_value(object, index, src_loc);
+        Self::resolve_optional(res, objectindex.optional)
+    }
+
+    fn query_slice(&mut self, slice: &ExprSlice, src_loc: &SourceLocationSpan) -> Result<QueryResult, Error> {
+        let start = self.interpret_slice_bound(&slice.start, src_loc)?;
+        let end = self.interpret_slice_bound(&slice.end, src_loc)?;
+        let object = match self.try_query(&slice.object, src_loc)? {
+            QueryResult::Found(object) => object,
+            QueryResult::Multi(objects) => {
+                return Self::map_multi(objects, slice.optional, |object| {
+                    Self::slice_value(object, start, end, src_loc)
+                })
+            }
+            res @ (QueryResult::Missing(_) | QueryResult::Skipped) => return Ok(res),
+        };
+
+        let res = Self::slice_value(object, start, end, src_loc);
+        Self::resolve_optional(res, slice.optional)
+    }
+
+    fn query_wildcard(&mut self, wildcard: &ExprWildcard, src_loc: &SourceLocationSpan) -> Result<QueryResult, Error> {
+        let object = match self.try_query(&wildcard.object, src_loc)? {
+            QueryResult::Found(object) => object,
+            QueryResult::Multi(objects) => {
+                return Self::map_multi(objects, wildcard.optional, |object| {
+                    Self::wildcard_value(object, src_loc)
+                })
+            }
+            res @ (QueryResult::Missing(_) | QueryResult::Skipped) => return Ok(res),
+        };
+
+        let res = Self::wildcard_value(object, src_loc);
+        Self::resolve_optional(res, wildcard.optional)
+    }
+
+    fn wildcard_value(object: Yaml, src_loc: &SourceLocationSpan) -> Result<QueryResult, Error> {
+        match object {
+            Yaml::Array(list) => Ok(QueryResult::Multi(list.as_ref().clone())),
+            Yaml::Hash(map) => Ok(QueryResult::Multi(map.values().cloned().collect())),
+            // Treated as a missing path, like indexing into null.
+            Yaml::Null => Ok(QueryResult::Missing(errwithloc!(
+                src_loc,
+                "cannot get [*]: value type {} is not a list or map",
+                Self::yaml_type_name(&object),
+            ))),
+            _ => Err(errwithloc!(
+                src_loc,
+                "cannot get [*]: value type {} is not a list or map",
+                Self::yaml_type_name(&object),
+            )),
+        }
+    }
+
+    fn query_descent(&mut self, descent: &ExprDescent, src_loc: &SourceLocationSpan) -> Result<QueryResult, Error> {
+        let index = self.interpret_expr(&descent.index, src_loc)?;
+        let objects = match self.try_query(&descent.object, src_loc)? {
+            QueryResult::Found(object) => vec![object],
+            QueryResult::Multi(objects) => objects,
+            res @ (QueryResult::Missing(_) | QueryResult::Skipped) => return Ok(res),
+        };
+
+        let mut values = Vec::new();
+        for object in objects {
+            Self::descend_value(object, &index, src_loc, &mut values);
+        }
+        Ok(QueryResult::Multi(values))
+    }
+
+    // Finds every value within the object (including the object itself) that has the index, in document order.
+    fn descend_value(object: Yaml, index: &ExprValue, src_loc: &SourceLocationSpan, values: &mut Vec<Yaml>) {
+        let children = match &object {
+            Yaml::Array(list) => list.as_ref().clone(),
+            Yaml::Hash(map) => map.values().cloned().collect(),
+            _ => return,
+        };
+
+        if let Ok(QueryResult::Found(value)) = Self::index_value(object, index.clone(), src_loc) {
+            values.push(value);
+        }
+
+        for child in children {
+            Self::descend_value(child, index, src_loc, values);
+        }
+    }
+
+    // Applies a query segment to each of the values matched by a wildcard or a recursive descent.
+    fn map_multi(
+        objects: Vec<Yaml>,
+        optional: bool,
+        mut segment: impl FnMut(Yaml) -> Result<QueryResult, Error>,
+    ) -> Result<QueryResult, Error> {
+        let mut values = Vec::new();
+        for object in objects {
+            match Self::resolve_optional(segment(object), optional)? {
+                QueryResult::Found(value) => values.push(value),
+                QueryResult::Multi(subvalues) => values.extend(subvalues),
+                // Values that an optional segment can't be resolved for are skipped.
+                QueryResult::Skipped => {}
+                missing @ QueryResult::Missing(_) => return Ok(missing),
+            }
+        }
+        Ok(QueryResult::Multi(values))
+    }
+
+    fn resolve_optional(res: Result<QueryResult, Error>, optional: bool) -> Result<QueryResult, Error> {
+        if optional {
+            // An optional segment that can't be resolved makes the whole query null.
+            return match res {
+                Ok(QueryResult::Missing(_)) | Err(_) => Ok(QueryResult::Skipped),
+                res => res,
+            };
+        }
+        res
+    }
+
+    fn interpret_slice_bound(
+        &mut self,
+        bound: &Option<Box<Expr>>,
+        src_loc: &SourceLocationSpan,
+    ) -> Result<Option<i64>, Error> {
+        let Some(bound) = bound else {
+            return Ok(None);
+        };
+
+        match self.interpret_expr(bound, src_loc)? {
+            ExprValue::Yaml(Yaml::Integer(bound)) => Ok(Some(bound)),
+            ExprValue::Yaml(Yaml::Null) => Ok(None),
+            bound => Err(errwithloc!(
+                src_loc,
+                "value of type {} cannot be used as a slice bound",
+                Self::exp_value_type_name(&bound),
+            )),
+        }
+    }
+
+    fn slice_value(
+        object: Yaml,
+        start: Option<i64>,
+        end: Option<i64>,
+        src_loc: &SourceLocationSpan,
+    ) -> Result<QueryResult, Error> {
+        match object {
+            Yaml::Array(list) => {
+                let (start, end) = Self::resolve_slice_bounds(start, end, list.len());
+                let sublist = list[start..end].to_vec();
+                Ok(QueryResult::Found(Yaml::Array(Rc::new(sublist))))
+            }
+            Yaml::String(string) => {
+                let chars: Vec<char> = string.chars().collect();
+                let (start, end) = Self::resolve_slice_bounds(start, end, chars.len());
+                let substring = chars[start..end].iter().collect();
+                Ok(QueryResult::Found(Yaml::String(Rc::new(substring))))
+            }
+            _ => Err(errwithloc!(
+                src_loc,
+                "cannot slice value of type {}",
+                Self::yaml_type_name(&object),
+            )),
+        }
+    }
+
+    // Converts a slice's bounds into a range within a list or string of the specified length. Like Python, negative
+    // bounds count back from the end and out of range bounds are clamped.
+    fn resolve_slice_bounds(start: Option<i64>, end: Option<i64>, len: usize) -> (usize, usize) {
+        let clamp = |bound: i64| {
+            let bound = match bound < 0 {
+                true => bound.saturating_add(len as i64),
+                false => bound,
+            };
+            bound.clamp(0, len as i64) as usize
+        };
+
+        let start = start.map_or(0, clamp);
+        let end = end.map_or(len, clamp);
+        (start, end.max(start))
+    }
+
+    // Converts an index into a position within a list or string of the specified length. Negative indexes count back
+    // from the end.
+    fn resolve_index(index: i64, len: usize) -> Option<usize> {
+        let index = match index < 0 {
+            true => index.checked_add(len as i64)?,
+            false => index,
+        };
+        usize::try_from(index).ok().filter(|index| *index < len)
+    }
+
+    fn index_value(object: Yaml, index: ExprValue, src_loc: &SourceLocationSpan) -> Result<QueryResult, Error> {
+        // Strings can only be indexed by integers. Other index types are reported as the string not being indexable,
+        // since they are more likely to be an attempt to get a map's field.
+        if let (Yaml::String(string), ExprValue::Yaml(Yaml::Integer(index))) = (&object, &index) {
+            return Ok(Self::index_string(string, *index, src_loc));
+        }
+
         match object {
             Yaml::Hash(object) => {
                 let index = match index {
@@ -511,12 +1348,12 @@ impl InterpreterRun {
 
                 let subvalue = object.get(&index);
                 match subvalue {
-                    Some(subvalue) => Ok(subvalue.clone()),
-                    None => Err(errwithloc!(
+                    Some(subvalue) => Ok(QueryResult::Found(subvalue.clone())),
+                    None => Ok(QueryResult::Missing(errwithloc!(
                         src_loc,
                         "index {} not found",
                         Self::yaml_debug_string(&index),
-                    )),
+                    ))),
                 }
             }
             Yaml::Array(list) => {
@@ -531,13 +1368,22 @@ impl InterpreterRun {
                     }
                 };
 
-                let index = usize::try_from(index)?;
-                let subvalue = list.get(index);
-                match subvalue {
-                    Some(subvalue) => Ok(subvalue.clone()),
-                    None => Err(errwithloc!(src_loc, "index {} is out of bounds", index)),
+                match Self::resolve_index(index, list.len()) {
+                    Some(position) => Ok(QueryResult::Found(list[position].clone())),
+                    None => Ok(QueryResult::Missing(errwithloc!(
+                        src_loc,
+                        "index {} is out of bounds",
+                        index
+                    ))),
                 }
             }
+            // Indexing into null is treated as a missing path, so that `??` can provide a default.
+            Yaml::Null => Ok(QueryResult::Missing(errwithloc!(
+                src_loc,
+                "cannot get index {}: value type {} is not indexable",
+                Self::expr_value_debug_string(&index),
+                Self::yaml_type_name(&object),
+            ))),
             _ => Err(errwithloc!(
                 src_loc,
                 "cannot get index {}: value type {} is not indexable",
@@ -547,10 +1393,37 @@ impl InterpreterRun {
         }
     }
 
+    fn index_string(string: &str, index: i64, src_loc: &SourceLocationSpan) -> QueryResult {
+        let len = string.chars().count();
+        match Self::resolve_index(index, len) {
+            Some(position) => {
+                let char = string.chars().nth(position).unwrap_or_default();
+                QueryResult::Found(Yaml::String(Rc::new(char.to_string())))
+            }
+            None => QueryResult::Missing(errwithloc!(src_loc, "index {} is out of bounds", index)),
+        }
+    }
+
+    fn interpret_coalesce(&mut self, op: &ExprOpBinary, src_loc: &SourceLocationSpan) -> Result<ExprValue, Error> {
+        let left = match op.left.as_ref() {
+            Expr::Query(query) => match self.try_query(query, src_loc)? {
+                QueryResult::Found(value) => ExprValue::Yaml(value),
+                QueryResult::Multi(values) => ExprValue::Yaml(Yaml::Array(Rc::new(values))),
+                QueryResult::Missing(_) | QueryResult::Skipped => ExprValue::Yaml(Yaml::Null),
+            },
+            left => self.interpret_expr(left, src_loc)?,
+        };
+
+        match left {
+            ExprValue::Yaml(Yaml::Null) => self.interpret_expr(&op.right, src_loc),
+            left => Ok(left),
+        }
+    }
+
     fn interpret_eq(&mut self, op: &ExprOpBinary, src_loc: &SourceLocationSpan) -> Result<ExprValue, Error> {
         let left = self.interpret_expr(&op.left, src_loc)?;
         let right = self.interpret_expr(&op.right, src_loc)?;
-        let res = left == right;
+        let res = Self::values_equal(&left, &right, src_loc)?;
         let res = ExprValue::Yaml(Yaml::Boolean(res));
         Ok(res)
     }
@@ -558,11 +1431,343 @@ impl InterpreterRun {
     fn interpret_ne(&mut self, op: &ExprOpBinary, src_loc: &SourceLocationSpan) -> Result<ExprValue, Error> {
         let left = self.interpret_expr(&op.left, src_loc)?;
         let right = self.interpret_expr(&op.right, src_loc)?;
-        let res = left != right;
+        let res = !Self::values_equal(&left, &right, src_loc)?;
         let res = ExprValue::Yaml(Yaml::Boolean(res));
         Ok(res)
     }
 
+    // Numbers are compared by value, so that `1 == 1.0` agrees with the ordering operators.
+    fn values_equal(left: &ExprValue, right: &ExprValue, src_loc: &SourceLocationSpan) -> Result<bool, Error> {
+        match (left, right) {
+            (ExprValue::Yaml(Yaml::Integer(left)), ExprValue::Yaml(Yaml::Integer(right))) => Ok(left == right),
+            (
+                ExprValue::Yaml(left @ (Yaml::Integer(_) | Yaml::Real(_))),
+                ExprValue::Yaml(right @ (Yaml::Integer(_) | Yaml::Real(_))),
+            ) => Ok(Self::number_to_f64(left, src_loc)? == Self::number_to_f64(right, src_loc)?),
+            _ => Ok(left == right),
+        }
+    }
+
+    fn interpret_compare(
+        &mut self,
+        op: &ExprOpBinary,
+        src_loc: &SourceLocationSpan,
+        accept: fn(Ordering) -> bool,
+    ) -> Result<ExprValue, Error> {
+        let left = self.interpret_expr(&op.left, src_loc)?;
+        let right = self.interpret_expr(&op.right, src_loc)?;
+        let ordering = Self::compare_values(&left, &right, src_loc)?;
+        let res = ExprValue::Yaml(Yaml::Boolean(accept(ordering)));
+        Ok(res)
+    }
+
+    fn compare_values(left: &ExprValue, right: &ExprValue, src_loc: &SourceLocationSpan) -> Result<Ordering, Error> {
+        match (left, right) {
+            (ExprValue::Yaml(Yaml::Integer(left)), ExprValue::Yaml(Yaml::Integer(right))) => Ok(left.cmp(right)),
+            (
+                ExprValue::Yaml(left @ (Yaml::Integer(_) | Yaml::Real(_))),
+                ExprValue::Yaml(right @ (Yaml::Integer(_) | Yaml::Real(_))),
+            ) => {
+                let left = Self::number_to_f64(left, src_loc)?;
+                let right = Self::number_to_f64(right, src_loc)?;
+                left.partial_cmp(&right)
+                    .ok_or_else(|| errwithloc!(src_loc, "cannot compare {} with {}", left, right))
+            }
+            (ExprValue::Yaml(Yaml::String(left)), ExprValue::Yaml(Yaml::String(right))) => Ok(left.cmp(right)),
+            _ => Err(errwithloc!(
+                src_loc,
+                "cannot compare value of type {} with value of type {}",
+                Self::exp_value_type_name(left),
+                Self::exp_value_type_name(right),
+            )),
+        }
+    }
+
+    fn interpret_in(&mut self, op: &ExprOpBinary, src_loc: &SourceLocationSpan) -> Result<ExprValue, Error> {
+        let res = self.is_member(op, src_loc, "in")?;
+        Ok(ExprValue::Yaml(Yaml::Boolean(res)))
+    }
+
+    fn interpret_not_in(&mut self, op: &ExprOpBinary, src_loc: &SourceLocationSpan) -> Result<ExprValue, Error> {
+        let res = self.is_member(op, src_loc, "not in")?;
+        Ok(ExprValue::Yaml(Yaml::Boolean(!res)))
+    }
+
+    // Checks if the left value is an item of a list, a key of a map or a substring of a string.
+    fn is_member(&mut self, op: &ExprOpBinary, src_loc: &SourceLocationSpan, op_name: &str) -> Result<bool, Error> {
+        let usage = format!("an operand of '{}'", op_name);
+        let left = self.interpret_expr(&op.left, src_loc)?;
+        let left = Self::expect_yaml(left, &usage, src_loc)?;
+        let right = self.interpret_expr(&op.right, src_loc)?;
+        let right = Self::expect_yaml(right, &usage, src_loc)?;
+
+        let res = match (&right, &left) {
+            (Yaml::Array(list), value) => list.contains(value),
+            (Yaml::Hash(map), key) => map.contains_key(key),
+            (Yaml::String(string), Yaml::String(substring)) => string.contains(substring.as_str()),
+            (Yaml::String(_), value) => {
+                return Err(errwithloc!(
+                    src_loc,
+                    "operator '{}' requires a string value when checking for a substring, found value of type {}",
+                    op_name,
+                    Self::yaml_type_name(value),
+                ))
+            }
+            (collection, _) => {
+                return Err(errwithloc!(
+                    src_loc,
+                    "operator '{}' requires a list, map or string collection, found value of type {}",
+                    op_name,
+                    Self::yaml_type_name(collection),
+                ))
+            }
+        };
+        Ok(res)
+    }
+
+    fn interpret_match(&mut self, expr_match: &ExprMatch, src_loc: &SourceLocationSpan) -> Result<ExprValue, Error> {
+        let value = self.interpret_expr(&expr_match.value, src_loc)?;
+        let pattern = self.interpret_expr(&expr_match.pattern, src_loc)?;
+        let (value, pattern) = match (value, pattern) {
+            (ExprValue::Yaml(Yaml::String(value)), ExprValue::Yaml(Yaml::String(pattern))) => (value, pattern),
+            (value, pattern) => {
+                return Err(errwithloc!(
+                    src_loc,
+                    "operator '=~' cannot be applied to values of type {} and {}",
+                    Self::exp_value_type_name(&value),
+                    Self::exp_value_type_name(&pattern),
+                ))
+            }
+        };
+
+        let regex = compile_regex(&pattern, &expr_match.regex_cache, src_loc)?;
+        let res = ExprValue::Yaml(Yaml::Boolean(regex.is_match(&value)));
+        Ok(res)
+    }
+
+    fn interpret_and(&mut self, op: &ExprOpBinary, src_loc: &SourceLocationSpan) -> Result<ExprValue, Error> {
+        // Short-circuit: The right value is only evaluated if the left value is true.
+        let left = self.interpret_expr(&op.left, src_loc)?;
+        if !Self::expect_implicit_bool(left.clone(), src_loc)? {
+            return Ok(left);
+        }
+
+        self.interpret_expr(&op.right, src_loc)
+    }
+
+    fn interpret_or(&mut self, op: &ExprOpBinary, src_loc: &SourceLocationSpan) -> Result<ExprValue, Error> {
+        // Short-circuit: The right value is only evaluated if the left value is false.
+        let left = self.interpret_expr(&op.left, src_loc)?;
+        if Self::expect_implicit_bool(left.clone(), src_loc)? {
+            return Ok(left);
+        }
+
+        self.interpret_expr(&op.right, src_loc)
+    }
+
+    fn interpret_conditional(
+        &mut self,
+        conditional: &ExprConditional,
+        src_loc: &SourceLocationSpan,
+    ) -> Result<ExprValue, Error> {
+        // Only the branch that is taken is evaluated.
+        let condition = self.interpret_expr(&conditional.condition, src_loc)?;
+        match Self::expect_implicit_bool(condition, src_loc)? {
+            true => self.interpret_expr(&conditional.true_value, src_loc),
+            false => self.interpret_expr(&conditional.false_value, src_loc),
+        }
+    }
+
+    fn interpret_not(&mut self, op: &ExprOpUnary, src_loc: &SourceLocationSpan) -> Result<ExprValue, Error> {
+        let value = self.interpret_expr(&op.value, src_loc)?;
+        let value = Self::expect_implicit_bool(value, src_loc)?;
+        let res = ExprValue::Yaml(Yaml::Boolean(!value));
+        Ok(res)
+    }
+
+    fn interpret_arithmetic(
+        &mut self,
+        op: &ExprOpBinary,
+        src_loc: &SourceLocationSpan,
+        arithmetic_op: ArithmeticOp,
+    ) -> Result<ExprValue, Error> {
+        let left = self.interpret_expr(&op.left, src_loc)?;
+        let right = self.interpret_expr(&op.right, src_loc)?;
+
+        let (left, right) = match (left, right) {
+            (
+                ExprValue::Yaml(left @ (Yaml::Integer(_) | Yaml::Real(_))),
+                ExprValue::Yaml(right @ (Yaml::Integer(_) | Yaml::Real(_))),
+            ) => (left, right),
+            (left, right) => {
+                return Err(errwithloc!(
+                    src_loc,
+                    "operator '{}' cannot be applied to values of type {} and {}",
+                    Self::arithmetic_op_name(arithmetic_op),
+                    Self::exp_value_type_name(&left),
+                    Self::exp_value_type_name(&right),
+                ))
+            }
+        };
+
+        if matches!(arithmetic_op, ArithmeticOp::Div | ArithmeticOp::Rem)
+            && Self::number_to_f64(&right, src_loc)? == 0.0
+        {
+            return Err(errwithloc!(src_loc, "division by zero"));
+        }
+
+        let res = match (left, right) {
+            (Yaml::Integer(left), Yaml::Integer(right)) => {
+                let res = match arithmetic_op {
+                    ArithmeticOp::Add => left.checked_add(right),
+                    ArithmeticOp::Sub => left.checked_sub(right),
+                    ArithmeticOp::Mul => left.checked_mul(right),
+                    ArithmeticOp::Div => left.checked_div(right),
+                    ArithmeticOp::Rem => left.checked_rem(right),
+                };
+                let res = res.ok_or_else(|| {
+                    errwithloc!(
+                        src_loc,
+                        "integer overflow: {} {} {}",
+                        left,
+                        Self::arithmetic_op_name(arithmetic_op),
+                        right
+                    )
+                })?;
+                Yaml::Integer(res)
+            }
+            (left, right) => {
+                let left = Self::number_to_f64(&left, src_loc)?;
+                let right = Self::number_to_f64(&right, src_loc)?;
+                let res = match arithmetic_op {
+                    ArithmeticOp::Add => left + right,
+                    ArithmeticOp::Sub => left - right,
+                    ArithmeticOp::Mul => left * right,
+                    ArithmeticOp::Div => left / right,
+                    ArithmeticOp::Rem => left % right,
+                };
+                Self::f64_to_real(res)
+            }
+        };
+
+        Ok(ExprValue::Yaml(res))
+    }
+
+    fn interpret_neg(&mut self, op: &ExprOpUnary, src_loc: &SourceLocationSpan) -> Result<ExprValue, Error> {
+        let value = self.interpret_expr(&op.value, src_loc)?;
+        let res = match value {
+            ExprValue::Yaml(Yaml::Integer(value)) => match value.checked_neg() {
+                Some(res) => Yaml::Integer(res),
+                None => return Err(errwithloc!(src_loc, "integer overflow: -{}", value)),
+            },
+            ExprValue::Yaml(value @ Yaml::Real(_)) => Self::f64_to_real(-Self::number_to_f64(&value, src_loc)?),
+            _ => {
+                return Err(errwithloc!(
+                    src_loc,
+                    "operator '-' cannot be applied to value of type {}",
+                    Self::exp_value_type_name(&value),
+                ))
+            }
+        };
+        Ok(ExprValue::Yaml(res))
+    }
+
+    fn interpret_call(&mut self, call: &ExprCall, src_loc: &SourceLocationSpan) -> Result<ExprValue, Error> {
+        let args = self.interpret_args(&call.args, src_loc)?;
+        self.call_function(call, args, src_loc)
+    }
+
+    fn interpret_pipe(&mut self, pipe: &ExprPipe, src_loc: &SourceLocationSpan) -> Result<ExprValue, Error> {
+        let value = self.interpret_expr(&pipe.value, src_loc)?;
+        let value = Self::expect_yaml(value, "a function argument", src_loc)?;
+
+        // The piped value is passed as the first argument.
+        let mut args = vec![value];
+        args.extend(self.interpret_args(&pipe.filter.args, src_loc)?);
+        self.call_function(&pipe.filter, args, src_loc)
+    }
+
+    fn interpret_args(&mut self, arg_exprs: &[Expr], src_loc: &SourceLocationSpan) -> Result<Vec<Yaml>, Error> {
+        let mut args = Vec::new();
+        for arg in arg_exprs {
+            let arg = self.interpret_expr(arg, src_loc)?;
+            let arg = Self::expect_yaml(arg, "a function argument", src_loc)?;
+            args.push(arg);
+        }
+        Ok(args)
+    }
+
+    fn call_function(
+        &mut self,
+        call: &ExprCall,
+        args: Vec<Yaml>,
+        src_loc: &SourceLocationSpan,
+    ) -> Result<ExprValue, Error> {
+        let Some(function) = find_function(&call.name) else {
+            return Err(errwithloc!(src_loc, "unknown function '{}'", call.name));
+        };
+
+        Self::check_arg_count(function, &args, src_loc)?;
+
+        let ctx = FunctionContext {
+            src_loc,
+            regex_cache: &call.regex_cache,
+        };
+        let res = (function.func)(&args, &ctx)?;
+        Ok(ExprValue::Yaml(res))
+    }
+
+    fn check_arg_count(function: &Function, args: &[Yaml], src_loc: &SourceLocationSpan) -> Result<(), Error> {
+        let min_args = function.params.len();
+        let max_args = match function.optional_params.last() {
+            Some(param) if param.ends_with("...") => usize::MAX,
+            _ => min_args + function.optional_params.len(),
+        };
+        if args.len() < min_args || args.len() > max_args {
+            let params = [function.params, function.optional_params].concat();
+            let expected = match (min_args == max_args, max_args == usize::MAX) {
+                (true, _) => min_args.to_string(),
+                (false, true) => format!("at least {}", min_args),
+                (false, false) => format!("{} to {}", min_args, max_args),
+            };
+            return Err(errwithloc!(
+                src_loc,
+                "function '{}' expects {} argument(s) ({}), found {}",
+                function.name,
+                expected,
+                params.join(", "),
+                args.len()
+            ));
+        }
+        Ok(())
+    }
+
+    fn interpret_list(&mut self, list: &ExprList, src_loc: &SourceLocationSpan) -> Result<ExprValue, Error> {
+        let mut items = Vec::new();
+        for item in &list.items {
+            let item = self.interpret_expr(item, src_loc)?;
+            let item = Self::expect_yaml(item, "a list item", src_loc)?;
+            items.push(item);
+        }
+
+        let res = ExprValue::Yaml(Yaml::Array(Rc::new(items)));
+        Ok(res)
+    }
+
+    fn interpret_map_literal(&mut self, map: &ExprMap, src_loc: &SourceLocationSpan) -> Result<ExprValue, Error> {
+        let mut entries = LinkedHashMap::new();
+        for entry in &map.entries {
+            let key = self.interpret_expr(&entry.key, src_loc)?;
+            let key = Self::expect_yaml(key, "a map key", src_loc)?;
+            let value = self.interpret_expr(&entry.value, src_loc)?;
+            let value = Self::expect_yaml(value, "a map value", src_loc)?;
+            entries.insert(key, value);
+        }
+
+        let res = ExprValue::Yaml(Yaml::Hash(Rc::new(entries)));
+        Ok(res)
+    }
+
     fn interpret_integer(&mut self, integer: &ExprInteger) -> Result<ExprValue, Error> {
         Ok(ExprValue::Yaml(Yaml::Integer(integer.value)))
     }
@@ -574,6 +1779,16 @@ impl InterpreterRun {
     fn push_scope(&mut self) {
         self.scopes.push(Scope {
             variables: HashMap::new(),
+            macros: HashMap::new(),
+            root: None,
+        });
+    }
+
+    fn push_root_scope(&mut self, root: Yaml) {
+        self.scopes.push(Scope {
+            variables: HashMap::new(),
+            macros: HashMap::new(),
+            root: Some(root),
         });
     }
 
@@ -581,15 +1796,155 @@ impl InterpreterRun {
         self.scopes.pop();
     }
 
-    fn add_binding(&mut self, binding: &ExprBinding, value: &Yaml) {
-        match binding {
-            ExprBinding::Var(name) => self
-                .scopes
-                .last_mut()
-                .unwrap()
-                .variables
-                .insert(name.as_ref().clone(), value.clone()),
+    // Flattens the visible variables, macros and root into a single scope.
+    fn capture_scope(&self) -> Scope {
+        let mut captured = Scope {
+            variables: HashMap::new(),
+            macros: HashMap::new(),
+            root: Some(self.query_root()),
         };
+        for scope in &self.scopes {
+            captured.variables.extend(scope.variables.clone());
+            captured.macros.extend(scope.macros.clone());
+        }
+        captured
+    }
+
+    fn add_macro(&mut self, name: &str, macro_def: Macro) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .macros
+            .insert(name.to_string(), Rc::new(macro_def));
+    }
+
+    fn find_macro(&self, name: &str) -> Option<Rc<Macro>> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.macros.get(name).cloned())
+    }
+
+    // The `item_index` is the index of the for loop item that is being bound, if any. It is used in error messages.
+    fn add_binding(
+        &mut self,
+        binding: &ExprBinding,
+        value: &Yaml,
+        item_index: Option<usize>,
+        src_loc: &SourceLocationSpan,
+    ) -> Result<(), Error> {
+        let location = || match item_index {
+            Some(index) => format!(" in for loop item at index {}", index),
+            None => String::new(),
+        };
+
+        match binding {
+            ExprBinding::Var(name) => {
+                self.scopes
+                    .last_mut()
+                    .unwrap()
+                    .variables
+                    .insert(name.as_ref().clone(), value.clone());
+            }
+            ExprBinding::Map(map_binding) => {
+                let Yaml::Hash(map) = value else {
+                    return Err(errwithloc!(
+                        src_loc,
+                        "cannot destructure value of type {} using a map pattern{}",
+                        Self::yaml_type_name(value),
+                        location()
+                    ));
+                };
+
+                for entry in &map_binding.entries {
+                    let Some(field_value) = map.get(&Yaml::String(entry.key.clone())) else {
+                        return Err(errwithloc!(src_loc, "field '{}' not found{}", entry.key, location()));
+                    };
+                    self.add_binding(&entry.binding, field_value, item_index, src_loc)?;
+                }
+            }
+            ExprBinding::List(list_binding) => {
+                let Yaml::Array(list) = value else {
+                    return Err(errwithloc!(
+                        src_loc,
+                        "cannot destructure value of type {} using a list pattern{}",
+                        Self::yaml_type_name(value),
+                        location()
+                    ));
+                };
+
+                if list.len() != list_binding.items.len() {
+                    return Err(errwithloc!(
+                        src_loc,
+                        "list pattern has {} item(s), but the list has {} item(s){}",
+                        list_binding.items.len(),
+                        list.len(),
+                        location()
+                    ));
+                }
+
+                for (item_binding, item) in list_binding.items.iter().zip(list.as_ref()) {
+                    self.add_binding(item_binding, item, item_index, src_loc)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Splits a list item into the values of a for loop's bindings.
+    fn expect_tuple(
+        item: &Yaml,
+        bindings_len: usize,
+        index: usize,
+        src_loc: &SourceLocationSpan,
+    ) -> Result<Vec<Yaml>, Error> {
+        match item {
+            Yaml::Array(tuple) if tuple.len() == bindings_len => Ok(tuple.as_ref().clone()),
+            Yaml::Array(tuple) => Err(errwithloc!(
+                src_loc,
+                "for loop item at index {} has {} value(s), but the loop has {} bindings",
+                index,
+                tuple.len(),
+                bindings_len
+            )),
+            _ => Err(errwithloc!(
+                src_loc,
+                "for loop item at index {} must be a list of {} values, found value of type {}",
+                index,
+                bindings_len,
+                Self::yaml_type_name(item)
+            )),
+        }
+    }
+
+    fn add_bindings(
+        &mut self,
+        bindings: &[ExprBinding],
+        values: &[Yaml],
+        item_index: usize,
+        src_loc: &SourceLocationSpan,
+    ) -> Result<(), Error> {
+        for (binding, value) in bindings.iter().zip(values) {
+            self.add_binding(binding, value, Some(item_index), src_loc)?;
+        }
+        Ok(())
+    }
+
+    // Adds the `$loop` variable, which describes the current iteration of a for loop.
+    fn add_loop_variable(&mut self, index: usize, length: usize) {
+        let mut loop_info = LinkedHashMap::new();
+        let mut insert = |name: &str, value| loop_info.insert(Yaml::String(Rc::new(name.to_string())), value);
+        insert("index", Yaml::Integer(index as i64));
+        insert("first", Yaml::Boolean(index == 0));
+        insert("last", Yaml::Boolean(index + 1 == length));
+        insert("length", Yaml::Integer(length as i64));
+
+        let loop_info = Yaml::Hash(Rc::new(loop_info));
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .variables
+            .insert("loop".to_string(), loop_info);
     }
 
     fn expect_value(value: Value) -> Result<Value, Error> {
@@ -607,6 +1962,30 @@ impl InterpreterRun {
                 value.src_loc,
                 "expression value 'for' can only be used as a map key"
             )),
+            ValueData::Let(..) => Err(errwithloc!(
+                value.src_loc,
+                "expression value 'let' can only be used as a map key"
+            )),
+            ValueData::With(..) => Err(errwithloc!(
+                value.src_loc,
+                "expression value 'with' can only be used as a map key"
+            )),
+            ValueData::Match(..) => Err(errwithloc!(
+                value.src_loc,
+                "expression value 'match' can only be used as a map key"
+            )),
+            ValueData::Define(..) => Err(errwithloc!(
+                value.src_loc,
+                "expression value 'define' can only be used as a map key"
+            )),
+            ValueData::Call(..) => Err(errwithloc!(
+                value.src_loc,
+                "expression value 'call' can only be used as a map key"
+            )),
+            ValueData::Include(..) => Err(errwithloc!(
+                value.src_loc,
+                "expression value 'include' can only be used as a map key"
+            )),
         }
     }
 
@@ -627,12 +2006,29 @@ impl InterpreterRun {
         }
     }
 
-    fn expect_iterable(expr_value: ExprValue, src_loc: &SourceLocationSpan) -> Result<Yaml, Error> {
+    fn expect_yaml(value: ExprValue, usage: &str, src_loc: &SourceLocationSpan) -> Result<Yaml, Error> {
+        match value {
+            ExprValue::Inline => Err(errwithloc!(
+                src_loc,
+                "expression value 'inline' cannot be used as {}",
+                usage
+            )),
+            ExprValue::Drop => Err(errwithloc!(
+                src_loc,
+                "expression value 'drop' cannot be used as {}",
+                usage
+            )),
+            ExprValue::Yaml(yaml) => Ok(yaml),
+        }
+    }
+
+    fn expect_iterable(expr_value: ExprValue, src_loc: &SourceLocationSpan) -> Result<Iterable, Error> {
         match expr_value {
             ExprValue::Inline => Err(errwithloc!(src_loc, "expression value 'inline' is not iteratable")),
             ExprValue::Drop => Err(errwithloc!(src_loc, "expression value 'drop' is not iteratable")),
             ExprValue::Yaml(yaml) => match yaml {
-                Yaml::Array(_) | Yaml::Hash(_) => Ok(yaml),
+                Yaml::Array(list) => Ok(Iterable::List(list)),
+                Yaml::Hash(map) => Ok(Iterable::Map(map)),
                 _ => Err(errwithloc!(
                     src_loc,
                     "value type {} is not iteratable",
@@ -642,6 +2038,60 @@ impl InterpreterRun {
         }
     }
 
+    fn number_to_f64(yaml: &Yaml, src_loc: &SourceLocationSpan) -> Result<f64, Error> {
+        match yaml {
+            Yaml::Integer(value) => Ok(*value as f64),
+            Yaml::Real(value) => {
+                // Handle YAML's special float values (e.g. .inf, -.inf, .nan).
+                let (sign, unsigned) = match value.strip_prefix('-') {
+                    Some(unsigned) => (-1.0, unsigned),
+                    None => (1.0, value.strip_prefix('+').unwrap_or(value)),
+                };
+                match unsigned {
+                    ".inf" | ".Inf" | ".INF" => Ok(sign * f64::INFINITY),
+                    ".nan" | ".NaN" | ".NAN" => Ok(f64::NAN),
+                    _ => value
+                        .parse::<f64>()
+                        .map_err(|_| errwithloc!(src_loc, "invalid number {}", value)),
+                }
+            }
+            _ => Err(errwithloc!(
+                src_loc,
+                "value of type {} is not a number",
+                Self::yaml_type_name(yaml)
+            )),
+        }
+    }
+
+    fn f64_to_real(value: f64) -> Yaml {
+        let string = if value.is_nan() {
+            ".nan".to_string()
+        } else if value.is_infinite() {
+            match value.is_sign_positive() {
+                true => ".inf".to_string(),
+                false => "-.inf".to_string(),
+            }
+        } else {
+            // Ensure the number is still a real number when the YAML is reloaded.
+            let string = format!("{:?}", value);
+            match string.contains(['.', 'e']) {
+                true => string,
+                false => format!("{}.0", string),
+            }
+        };
+        Yaml::Real(Rc::new(string))
+    }
+
+    fn arithmetic_op_name(arithmetic_op: ArithmeticOp) -> &'static str {
+        match arithmetic_op {
+            ArithmeticOp::Add => "+",
+            ArithmeticOp::Sub => "-",
+            ArithmeticOp::Mul => "*",
+            ArithmeticOp::Div => "/",
+            ArithmeticOp::Rem => "%",
+        }
+    }
+
     fn expr_value_debug_string(value: &ExprValue) -> String {
         match value {
             ExprValue::Inline => "inline".to_string(),
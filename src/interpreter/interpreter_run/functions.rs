@@ -0,0 +1,502 @@
+// Copyright (c) Chris Gunn.
+// Licensed under the MIT license.
+
+// The built-in functions that can be called from template expressions.
+
+use std::rc::Rc;
+
+use anyhow::{anyhow, Error};
+use regex::Regex;
+
+use crate::{
+    ast::{RegexCache, SourceLocationSpan},
+    cow_yaml::Yaml,
+};
+
+use super::InterpreterRun;
+
+pub struct Function {
+    pub name: &'static str,
+    pub params: &'static [&'static str],
+    // Params that follow `params` and may be omitted.
+    // A final param ending with "..." can be repeated any number of times.
+    pub optional_params: &'static [&'static str],
+    pub func: fn(&[Yaml], &FunctionContext) -> Result<Yaml, Error>,
+}
+
+// Information about the call site that is passed to a function.
+pub struct FunctionContext<'a> {
+    pub src_loc: &'a SourceLocationSpan,
+    pub regex_cache: &'a RegexCache,
+}
+
+static FUNCTIONS: &[Function] = &[
+    Function {
+        name: "len",
+        params: &["value"],
+        optional_params: &[],
+        func: len,
+    },
+    Function {
+        name: "keys",
+        params: &["map"],
+        optional_params: &[],
+        func: keys,
+    },
+    Function {
+        name: "values",
+        params: &["map"],
+        optional_params: &[],
+        func: values,
+    },
+    Function {
+        name: "type",
+        params: &["value"],
+        optional_params: &[],
+        func: type_name,
+    },
+    Function {
+        name: "default",
+        params: &["value", "default"],
+        optional_params: &[],
+        func: default,
+    },
+    Function {
+        name: "has",
+        params: &["object", "index"],
+        optional_params: &[],
+        func: has,
+    },
+    Function {
+        name: "lower",
+        params: &["string"],
+        optional_params: &[],
+        func: lower,
+    },
+    Function {
+        name: "upper",
+        params: &["string"],
+        optional_params: &[],
+        func: upper,
+    },
+    Function {
+        name: "trim",
+        params: &["string"],
+        optional_params: &[],
+        func: trim,
+    },
+    Function {
+        name: "split",
+        params: &["string", "separator"],
+        optional_params: &[],
+        func: split,
+    },
+    Function {
+        name: "join",
+        params: &["list", "separator"],
+        optional_params: &[],
+        func: join,
+    },
+    Function {
+        name: "replace",
+        params: &["string", "from", "to"],
+        optional_params: &[],
+        func: replace,
+    },
+    Function {
+        name: "contains",
+        params: &["collection", "value"],
+        optional_params: &[],
+        func: contains,
+    },
+    Function {
+        name: "starts_with",
+        params: &["string", "prefix"],
+        optional_params: &[],
+        func: starts_with,
+    },
+    Function {
+        name: "ends_with",
+        params: &["string", "suffix"],
+        optional_params: &[],
+        func: ends_with,
+    },
+    Function {
+        name: "regex_match",
+        params: &["string", "pattern"],
+        optional_params: &[],
+        func: regex_match,
+    },
+    Function {
+        name: "regex_capture",
+        params: &["string", "pattern"],
+        optional_params: &[],
+        func: regex_capture,
+    },
+    Function {
+        name: "regex_replace",
+        params: &["string", "pattern", "replacement"],
+        optional_params: &[],
+        func: regex_replace,
+    },
+    Function {
+        name: "range",
+        params: &["start", "end"],
+        optional_params: &["step"],
+        func: range,
+    },
+    Function {
+        name: "zip",
+        params: &["list1", "list2"],
+        optional_params: &["lists..."],
+        func: zip,
+    },
+    Function {
+        name: "product",
+        params: &["list1", "list2"],
+        optional_params: &["lists..."],
+        func: product,
+    },
+];
+
+// The maximum number of items that a generated list (e.g. a range) can have.
+// This protects against templates that accidentally produce huge outputs.
+const MAX_GENERATED_LEN: usize = 100_000;
+
+// A sequence of integers from `start` (inclusive) to `end` (exclusive), in increments of `step`.
+pub struct Range {
+    pub start: i64,
+    pub end: i64,
+    pub step: i64,
+}
+
+impl Range {
+    pub fn len(&self) -> usize {
+        // Use i128 to avoid overflows.
+        let distance = (self.end as i128 - self.start as i128) * self.step.signum() as i128;
+        let step = (self.step as i128).abs();
+        match distance <= 0 {
+            true => 0,
+            false => usize::try_from((distance - 1) / step + 1).unwrap_or(usize::MAX),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = i64> {
+        let (start, step) = (self.start, self.step);
+        (0..self.len() as i64).map(move |index| start + index * step)
+    }
+}
+
+pub fn find_function(name: &str) -> Option<&'static Function> {
+    FUNCTIONS.iter().find(|function| function.name == name)
+}
+
+fn len(args: &[Yaml], ctx: &FunctionContext) -> Result<Yaml, Error> {
+    let len = match &args[0] {
+        Yaml::String(string) => string.chars().count(),
+        Yaml::Array(list) => list.len(),
+        Yaml::Hash(map) => map.len(),
+        value => return Err(wrong_type("len", "value", "a string, list or map", value, ctx.src_loc)),
+    };
+    Ok(Yaml::Integer(len as i64))
+}
+
+fn keys(args: &[Yaml], ctx: &FunctionContext) -> Result<Yaml, Error> {
+    match &args[0] {
+        Yaml::Hash(map) => Ok(Yaml::Array(Rc::new(map.keys().cloned().collect()))),
+        value => Err(wrong_type("keys", "map", "a map", value, ctx.src_loc)),
+    }
+}
+
+fn values(args: &[Yaml], ctx: &FunctionContext) -> Result<Yaml, Error> {
+    match &args[0] {
+        Yaml::Hash(map) => Ok(Yaml::Array(Rc::new(map.values().cloned().collect()))),
+        value => Err(wrong_type("values", "map", "a map", value, ctx.src_loc)),
+    }
+}
+
+fn type_name(args: &[Yaml], _ctx: &FunctionContext) -> Result<Yaml, Error> {
+    let name = InterpreterRun::yaml_type_name(&args[0]);
+    Ok(Yaml::String(Rc::new(name.to_string())))
+}
+
+fn default(args: &[Yaml], _ctx: &FunctionContext) -> Result<Yaml, Error> {
+    match &args[0] {
+        Yaml::Null => Ok(args[1].clone()),
+        value => Ok(value.clone()),
+    }
+}
+
+fn has(args: &[Yaml], ctx: &FunctionContext) -> Result<Yaml, Error> {
+    let res = match (&args[0], &args[1]) {
+        (Yaml::Hash(map), key) => map.contains_key(key),
+        (Yaml::Array(list), Yaml::Integer(index)) => InterpreterRun::resolve_index(*index, list.len()).is_some(),
+        (Yaml::Array(_), index) => return Err(wrong_type("has", "index", "an integer", index, ctx.src_loc)),
+        (object, _) => return Err(wrong_type("has", "object", "a list or map", object, ctx.src_loc)),
+    };
+    Ok(Yaml::Boolean(res))
+}
+
+fn lower(args: &[Yaml], ctx: &FunctionContext) -> Result<Yaml, Error> {
+    let string = expect_string("lower", "string", &args[0], ctx.src_loc)?;
+    Ok(Yaml::String(Rc::new(string.to_lowercase())))
+}
+
+fn upper(args: &[Yaml], ctx: &FunctionContext) -> Result<Yaml, Error> {
+    let string = expect_string("upper", "string", &args[0], ctx.src_loc)?;
+    Ok(Yaml::String(Rc::new(string.to_uppercase())))
+}
+
+fn trim(args: &[Yaml], ctx: &FunctionContext) -> Result<Yaml, Error> {
+    let string = expect_string("trim", "string", &args[0], ctx.src_loc)?;
+    Ok(Yaml::String(Rc::new(string.trim().to_string())))
+}
+
+fn split(args: &[Yaml], ctx: &FunctionContext) -> Result<Yaml, Error> {
+    let string = expect_string("split", "string", &args[0], ctx.src_loc)?;
+    let separator = expect_string("split", "separator", &args[1], ctx.src_loc)?;
+    let parts = string
+        .split(separator.as_str())
+        .map(|part| Yaml::String(Rc::new(part.to_string())))
+        .collect();
+    Ok(Yaml::Array(Rc::new(parts)))
+}
+
+fn join(args: &[Yaml], ctx: &FunctionContext) -> Result<Yaml, Error> {
+    let list = match &args[0] {
+        Yaml::Array(list) => list,
+        value => return Err(wrong_type("join", "list", "a list", value, ctx.src_loc)),
+    };
+    let separator = expect_string("join", "separator", &args[1], ctx.src_loc)?;
+
+    let mut parts = Vec::new();
+    for item in list.as_ref() {
+        let part = match item {
+            Yaml::String(value) | Yaml::Real(value) => value.as_ref().clone(),
+            Yaml::Integer(value) => value.to_string(),
+            Yaml::Boolean(value) => value.to_string(),
+            _ => {
+                return Err(wrong_type(
+                    "join",
+                    "list",
+                    "a list of strings, numbers or bools",
+                    item,
+                    ctx.src_loc,
+                ))
+            }
+        };
+        parts.push(part);
+    }
+    Ok(Yaml::String(Rc::new(parts.join(separator))))
+}
+
+fn replace(args: &[Yaml], ctx: &FunctionContext) -> Result<Yaml, Error> {
+    let string = expect_string("replace", "string", &args[0], ctx.src_loc)?;
+    let from = expect_string("replace", "from", &args[1], ctx.src_loc)?;
+    let to = expect_string("replace", "to", &args[2], ctx.src_loc)?;
+    Ok(Yaml::String(Rc::new(string.replace(from.as_str(), to))))
+}
+
+fn contains(args: &[Yaml], ctx: &FunctionContext) -> Result<Yaml, Error> {
+    let res = match (&args[0], &args[1]) {
+        (Yaml::String(string), Yaml::String(substring)) => string.contains(substring.as_str()),
+        (Yaml::String(_), value) => return Err(wrong_type("contains", "value", "a string", value, ctx.src_loc)),
+        (Yaml::Array(list), value) => list.contains(value),
+        (Yaml::Hash(map), key) => map.contains_key(key),
+        (collection, _) => {
+            return Err(wrong_type(
+                "contains",
+                "collection",
+                "a string, list or map",
+                collection,
+                ctx.src_loc,
+            ))
+        }
+    };
+    Ok(Yaml::Boolean(res))
+}
+
+fn starts_with(args: &[Yaml], ctx: &FunctionContext) -> Result<Yaml, Error> {
+    let string = expect_string("starts_with", "string", &args[0], ctx.src_loc)?;
+    let prefix = expect_string("starts_with", "prefix", &args[1], ctx.src_loc)?;
+    Ok(Yaml::Boolean(string.starts_with(prefix.as_str())))
+}
+
+fn ends_with(args: &[Yaml], ctx: &FunctionContext) -> Result<Yaml, Error> {
+    let string = expect_string("ends_with", "string", &args[0], ctx.src_loc)?;
+    let suffix = expect_string("ends_with", "suffix", &args[1], ctx.src_loc)?;
+    Ok(Yaml::Boolean(string.ends_with(suffix.as_str())))
+}
+
+fn regex_match(args: &[Yaml], ctx: &FunctionContext) -> Result<Yaml, Error> {
+    let string = expect_string("regex_match", "string", &args[0], ctx.src_loc)?;
+    let pattern = expect_string("regex_match", "pattern", &args[1], ctx.src_loc)?;
+    let regex = compile_regex(pattern, ctx.regex_cache, ctx.src_loc)?;
+    Ok(Yaml::Boolean(regex.is_match(string)))
+}
+
+// Returns the text matched by the pattern's first capture group, or the whole match if the pattern doesn't have any
+// capture groups. Returns null if the pattern doesn't match.
+fn regex_capture(args: &[Yaml], ctx: &FunctionContext) -> Result<Yaml, Error> {
+    let string = expect_string("regex_capture", "string", &args[0], ctx.src_loc)?;
+    let pattern = expect_string("regex_capture", "pattern", &args[1], ctx.src_loc)?;
+    let regex = compile_regex(pattern, ctx.regex_cache, ctx.src_loc)?;
+
+    let Some(captures) = regex.captures(string) else {
+        return Ok(Yaml::Null);
+    };
+
+    let group = match captures.len() {
+        1 => captures.get(0),
+        _ => captures.get(1),
+    };
+    match group {
+        Some(group) => Ok(Yaml::String(Rc::new(group.as_str().to_string()))),
+        None => Ok(Yaml::Null),
+    }
+}
+
+fn regex_replace(args: &[Yaml], ctx: &FunctionContext) -> Result<Yaml, Error> {
+    let string = expect_string("regex_replace", "string", &args[0], ctx.src_loc)?;
+    let pattern = expect_string("regex_replace", "pattern", &args[1], ctx.src_loc)?;
+    let replacement = expect_string("regex_replace", "replacement", &args[2], ctx.src_loc)?;
+    let regex = compile_regex(pattern, ctx.regex_cache, ctx.src_loc)?;
+    let res = regex.replace_all(string, replacement.as_str());
+    Ok(Yaml::String(Rc::new(res.into_owned())))
+}
+
+fn range(args: &[Yaml], ctx: &FunctionContext) -> Result<Yaml, Error> {
+    let range = new_range(args, ctx.src_loc)?;
+    let list = range.iter().map(Yaml::Integer).collect();
+    Ok(Yaml::Array(Rc::new(list)))
+}
+
+pub fn new_range(args: &[Yaml], src_loc: &SourceLocationSpan) -> Result<Range, Error> {
+    let mut ints = Vec::new();
+    for (arg, param) in args.iter().zip(["start", "end", "step"]) {
+        match arg {
+            Yaml::Integer(value) => ints.push(*value),
+            _ => return Err(wrong_type("range", param, "an integer", arg, src_loc)),
+        }
+    }
+
+    let range = Range {
+        start: ints[0],
+        end: ints[1],
+        step: ints.get(2).copied().unwrap_or(1),
+    };
+    if range.step == 0 {
+        return Err(errwithloc!(
+            src_loc,
+            "function 'range' expects argument 'step' to be non-zero"
+        ));
+    }
+    if range.len() > MAX_GENERATED_LEN {
+        return Err(errwithloc!(
+            src_loc,
+            "range has {} items, which exceeds the limit of {}",
+            range.len(),
+            MAX_GENERATED_LEN
+        ));
+    }
+    Ok(range)
+}
+
+// Returns a list of tuples, where the nth tuple contains the nth item of each list.
+// Stops at the end of the shortest list.
+fn zip(args: &[Yaml], ctx: &FunctionContext) -> Result<Yaml, Error> {
+    let lists = expect_lists("zip", args, ctx.src_loc)?;
+    let len = lists.iter().map(|list| list.len()).min().unwrap_or(0);
+    let tuples = (0..len)
+        .map(|index| {
+            let tuple = lists.iter().map(|list| list[index].clone()).collect();
+            Yaml::Array(Rc::new(tuple))
+        })
+        .collect();
+    Ok(Yaml::Array(Rc::new(tuples)))
+}
+
+// Returns a list of tuples for every combination of items from the lists (i.e. the cartesian product).
+fn product(args: &[Yaml], ctx: &FunctionContext) -> Result<Yaml, Error> {
+    let lists = expect_lists("product", args, ctx.src_loc)?;
+    let len = lists
+        .iter()
+        .try_fold(1usize, |len, list| len.checked_mul(list.len()))
+        .unwrap_or(usize::MAX);
+    if len > MAX_GENERATED_LEN {
+        return Err(errwithloc!(
+            ctx.src_loc,
+            "product has {} items, which exceeds the limit of {}",
+            len,
+            MAX_GENERATED_LEN
+        ));
+    }
+
+    let mut tuples = vec![Vec::new()];
+    for list in lists {
+        tuples = tuples
+            .iter()
+            .flat_map(|tuple| {
+                list.iter().map(|item| {
+                    let mut tuple = tuple.clone();
+                    tuple.push(item.clone());
+                    tuple
+                })
+            })
+            .collect();
+    }
+
+    let tuples = tuples.into_iter().map(|tuple| Yaml::Array(Rc::new(tuple))).collect();
+    Ok(Yaml::Array(Rc::new(tuples)))
+}
+
+pub fn compile_regex(pattern: &str, regex_cache: &RegexCache, src_loc: &SourceLocationSpan) -> Result<Regex, Error> {
+    regex_cache.get_or_compile(pattern).map_err(|err| {
+        // The regex crate's syntax errors span multiple lines. The last line holds the description.
+        let err = err.to_string();
+        let description = err.lines().last().unwrap_or_default();
+        let description = description.strip_prefix("error: ").unwrap_or(description);
+        errwithloc!(src_loc, "invalid regex pattern {:?}: {}", pattern, description)
+    })
+}
+
+fn expect_lists<'a>(
+    function: &str,
+    args: &'a [Yaml],
+    src_loc: &SourceLocationSpan,
+) -> Result<Vec<&'a Vec<Yaml>>, Error> {
+    let mut lists = Vec::new();
+    for (index, arg) in args.iter().enumerate() {
+        match arg {
+            Yaml::Array(list) => lists.push(list.as_ref()),
+            _ => {
+                let param = format!("list{}", index + 1);
+                return Err(wrong_type(function, &param, "a list", arg, src_loc));
+            }
+        }
+    }
+    Ok(lists)
+}
+
+fn expect_string<'a>(
+    function: &str,
+    param: &str,
+    value: &'a Yaml,
+    src_loc: &SourceLocationSpan,
+) -> Result<&'a Rc<String>, Error> {
+    match value {
+        Yaml::String(string) => Ok(string),
+        _ => Err(wrong_type(function, param, "a string", value, src_loc)),
+    }
+}
+
+fn wrong_type(function: &str, param: &str, expected: &str, value: &Yaml, src_loc: &SourceLocationSpan) -> Error {
+    errwithloc!(
+        src_loc,
+        "function '{}' expects argument '{}' to be {}, found value of type {}",
+        function,
+        param,
+        expected,
+        InterpreterRun::yaml_type_name(value)
+    )
+}
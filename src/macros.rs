@@ -0,0 +1,9 @@
+// Copyright (c) Chris Gunn.
+// Licensed under the MIT license.
+
+// Creates an error whose message is prefixed with a source location.
+macro_rules! errwithloc {
+    ($loc:expr, $fmt:expr $(, $($arg:tt)*)?) => {
+        anyhow!(concat!("{}:{}:{} ", $fmt), $loc.filename, $loc.start.line, $loc.start.col, $($($arg)*)?)
+    };
+}
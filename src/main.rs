@@ -1,6 +1,9 @@
 // Copyright (c) Chris Gunn.
 // Licensed under the MIT license.
 
+#[macro_use]
+mod macros;
+
 mod ast;
 mod cow_yaml;
 mod interpreter;
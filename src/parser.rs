@@ -6,11 +6,15 @@ mod template_expr;
 use std::{rc::Rc, str::Chars};
 
 use anyhow::{anyhow, Error};
-use saphyr_parser::{parser::Parser as YamlParser, scanner::Marker, Event};
+use saphyr_parser::{
+    parser::Parser as YamlParser,
+    scanner::{Marker, TScalarStyle},
+    Event,
+};
 
 use crate::ast::{
     DocumentTemplate, FileTemplate, MapEntryTemplate, MapTemplate, NodeTemplate, ScalarTemplateValue, ScalerTemplate,
-    SequenceTemplate, SourceLocation, SourceLocationSpan,
+    SequenceTemplate, SourceLocation, SourceLocationSpan, Statement,
 };
 
 use template_expr::parser::TemplateExprParser;
@@ -83,6 +87,10 @@ impl ParserRun<'_> {
         // Parse node.
         let node = self.parse_node(yaml_parser)?;
 
+        if let Some(key) = Self::single_entry_key(&node) {
+            Self::check_branch(key, None)?;
+        }
+
         // Parse DocumentEnd.
         let (doc_start, end) = yaml_parser.next_token()?;
         assert_eq!(doc_start, Event::DocumentEnd);
@@ -136,6 +144,14 @@ impl ParserRun<'_> {
         let (seq_end, end) = yaml_parser.next_token()?;
         assert_eq!(seq_end, Event::SequenceEnd);
 
+        // An if chain can span multiple list items, where each item is a single entry map.
+        for (i, value) in values.iter().enumerate() {
+            if let Some(key) = Self::single_entry_key(value) {
+                let prev_key = i.checked_sub(1).and_then(|j| Self::single_entry_key(&values[j]));
+                Self::check_branch(key, prev_key)?;
+            }
+        }
+
         // Return result.
         let src_loc = self.to_source_location_span(&start, &end);
         let seq = SequenceTemplate { src_loc, values };
@@ -175,6 +191,18 @@ impl ParserRun<'_> {
         let (map_end, end) = yaml_parser.next_token()?;
         assert_eq!(map_end, Event::MappingEnd);
 
+        for (i, entry) in entries.iter().enumerate() {
+            // A single entry map's key is checked by its parent, since the map may be an item in an if chain list.
+            if entries.len() > 1 {
+                let prev_key = i.checked_sub(1).map(|j| &entries[j].key);
+                Self::check_branch(&entry.key, prev_key)?;
+            }
+
+            if let Some(key) = Self::single_entry_key(&entry.value) {
+                Self::check_branch(key, None)?;
+            }
+        }
+
         let mut src_loc = self.to_source_location_span(&start, &end);
 
         // In YAML, you don't know that you are parsing a map until you see the first colon ':' character.
@@ -191,10 +219,19 @@ impl ParserRun<'_> {
     fn parse_scaler(&self, yaml_parser: &mut YamlParser<Chars>) -> Result<ScalerTemplate, Error> {
         // Parse Scalar.
         let (scalar, start) = yaml_parser.next_token()?;
-        let Event::Scalar(value, _, _, _) = scalar else {
+        let Event::Scalar(value, style, _, _) = scalar else {
             unreachable!()
         };
 
+        let (_, end) = yaml_parser.peek()?;
+        let src_loc = self.to_source_location_span(&start, end);
+
+        // The YAML parser reports an empty node as `~`. A quoted "~" is kept as a string.
+        if style == TScalarStyle::Plain && value == "~" {
+            let values = vec![ScalarTemplateValue::Null];
+            return Ok(ScalerTemplate { src_loc, values });
+        }
+
         let mut curr_index = 0;
         let mut values = Vec::new();
         loop {
@@ -213,10 +250,16 @@ impl ParserRun<'_> {
 
             // Add template expression.
             let expr_str = &value[template_expr_index..];
-            let (expr, end) = self.expr_parser.parse(expr_str)?;
+            let (expr, end) = self
+                .expr_parser
+                .parse(expr_str)
+                .map_err(|err| errwithloc!(src_loc, "{}", err))?;
             let value = ScalarTemplateValue::Expr(expr);
             values.push(value);
 
+            // The expression parser returns a char index. So, convert it to a byte index.
+            let end = expr_str.char_indices().nth(end).map_or(expr_str.len(), |(i, _)| i);
+
             curr_index = template_expr_index + end;
         }
 
@@ -227,13 +270,32 @@ impl ParserRun<'_> {
             values.push(value);
         }
 
-        let (_, end) = yaml_parser.peek()?;
-
-        let src_loc = self.to_source_location_span(&start, end);
         let scalar = ScalerTemplate { src_loc, values };
         Ok(scalar)
     }
 
+    // Returns the key of a map that has only a single entry.
+    fn single_entry_key(node: &NodeTemplate) -> Option<&NodeTemplate> {
+        match node {
+            NodeTemplate::Map(MapTemplate { entries, .. }) if entries.len() == 1 => Some(&entries[0].key),
+            _ => None,
+        }
+    }
+
+    // Checks that an 'elif' or 'else' key directly follows an 'if' or 'elif' key.
+    fn check_branch(key: &NodeTemplate, prev_key: Option<&NodeTemplate>) -> Result<(), Error> {
+        let name = match key.statement() {
+            Some(Statement::Elif(_)) => "elif",
+            Some(Statement::Else) => "else",
+            _ => return Ok(()),
+        };
+
+        match prev_key.and_then(NodeTemplate::statement) {
+            Some(Statement::If(_)) | Some(Statement::Elif(_)) => Ok(()),
+            _ => Err(errwithloc!(key.src_loc(), "'{}' must follow an 'if' or 'elif'", name)),
+        }
+    }
+
     fn to_source_location_span(&self, start: &Marker, end: &Marker) -> SourceLocationSpan {
         SourceLocationSpan {
             filename: self.filename.clone(),
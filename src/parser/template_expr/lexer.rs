@@ -45,12 +45,31 @@ pub enum Token {
     Dot,
     Eq,
     Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
     LBracket,
     RBracket,
     Integer(i64),
     Real(String),
     Variable(String),
     Comma,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    LParen,
+    RParen,
+    Pipe,
+    LBrace,
+    RBrace,
+    Colon,
+    Coalesce,
+    Question,
+    Match,
+    Assign,
 }
 
 impl std::fmt::Display for Token {
@@ -63,12 +82,31 @@ impl std::fmt::Display for Token {
             Token::Dot => f.write_str("."),
             Token::Eq => f.write_str("=="),
             Token::Ne => f.write_str("!="),
+            Token::Lt => f.write_str("<"),
+            Token::Le => f.write_str("<="),
+            Token::Gt => f.write_str(">"),
+            Token::Ge => f.write_str(">="),
             Token::LBracket => f.write_str("["),
             Token::RBracket => f.write_str("]"),
             Token::Integer(i) => write!(f, "{}", i),
             Token::Real(string) => f.write_str(string),
             Token::Variable(name) => write!(f, "${}", name),
             Token::Comma => f.write_str(","),
+            Token::Plus => f.write_str("+"),
+            Token::Minus => f.write_str("-"),
+            Token::Star => f.write_str("*"),
+            Token::Slash => f.write_str("/"),
+            Token::Percent => f.write_str("%"),
+            Token::LParen => f.write_str("("),
+            Token::RParen => f.write_str(")"),
+            Token::Pipe => f.write_str("|"),
+            Token::LBrace => f.write_str("{"),
+            Token::RBrace => f.write_str("}"),
+            Token::Colon => f.write_str(":"),
+            Token::Coalesce => f.write_str("??"),
+            Token::Question => f.write_str("?"),
+            Token::Match => f.write_str("=~"),
+            Token::Assign => f.write_str("="),
         }
     }
 }
@@ -84,15 +122,19 @@ pub fn gen_lexer() -> impl Parser<char, Vec<(Token, Range<usize>)>, Error = Simp
         .chain(just('+').or(just('-')).or_not())
         .chain::<char, _, _>(text::digits(10));
 
-    let number = just('-')
-        .or_not()
-        .chain::<char, _, _>(text::int(10))
+    // Negative numbers are handled by the parser's unary minus operator.
+    let number = text::int(10)
         .chain::<char, _, _>(frac.or_not().flatten())
         .chain::<char, _, _>(exp.or_not().flatten())
         .collect::<String>()
-        .map(|string| match string.parse::<i64>() {
+        .validate(|string, span, emit| match string.parse::<i64>() {
             Ok(i) => Token::Integer(i),
-            Err(_) => Token::Real(string),
+            Err(_) => {
+                if string.chars().all(|c| c.is_ascii_digit()) {
+                    emit(Simple::custom(span, "integer literal out of range"));
+                }
+                Token::Real(string)
+            }
         })
         .labelled("number");
 
@@ -130,21 +172,40 @@ pub fn gen_lexer() -> impl Parser<char, Vec<(Token, Range<usize>)>, Error = Simp
 
     let variable = just("$").ignore_then(text::ident()).map(|name| Token::Variable(name));
 
-    let op = one_of("!=")
+    let op = one_of("!=<>~")
         .repeated()
         .at_least(1)
         .collect::<String>()
         .try_map(|s, span| match s.as_str() {
             "==" => Ok(Token::Eq),
             "!=" => Ok(Token::Ne),
+            "<" => Ok(Token::Lt),
+            "<=" => Ok(Token::Le),
+            ">" => Ok(Token::Gt),
+            ">=" => Ok(Token::Ge),
+            "=~" => Ok(Token::Match),
+            "=" => Ok(Token::Assign),
             _ => Err(Simple::custom(span, format!("unknown operator {}", s))),
         });
 
-    let ctrl = one_of(".,[]").map(|c| match c {
+    let question = just("??").to(Token::Coalesce).or(just("?").to(Token::Question));
+
+    let ctrl = one_of(".,[](){}:+-*/%|").map(|c| match c {
         '.' => Token::Dot,
         ',' => Token::Comma,
         '[' => Token::LBracket,
         ']' => Token::RBracket,
+        '(' => Token::LParen,
+        ')' => Token::RParen,
+        '{' => Token::LBrace,
+        '}' => Token::RBrace,
+        ':' => Token::Colon,
+        '+' => Token::Plus,
+        '-' => Token::Minus,
+        '*' => Token::Star,
+        '/' => Token::Slash,
+        '%' => Token::Percent,
+        '|' => Token::Pipe,
         _ => unreachable!(),
     });
 
@@ -155,6 +216,7 @@ pub fn gen_lexer() -> impl Parser<char, Vec<(Token, Range<usize>)>, Error = Simp
         .or(variable)
         .or(ident)
         .or(ctrl)
+        .or(question)
         .or(op);
 
     let token = token.map_with_span(|tok, span| (tok, span)).padded().repeated();
@@ -4,14 +4,16 @@
 #[cfg(test)]
 mod tests;
 
-use std::{ops::Range, rc::Rc};
+use std::{fmt::Display, hash::Hash, ops::Range, rc::Rc};
 
 use anyhow::{anyhow, Error};
-use chumsky::{prelude::*, Stream};
+use chumsky::{error::SimpleReason, prelude::*, Stream};
 
 use crate::ast::{
-    Expr, ExprBinding, ExprIndex, ExprInteger, ExprOpBinary, ExprQuery, ExprReal, ExprString, Statement, StatementFor,
-    StatementIf,
+    Expr, ExprBinding, ExprBindingList, ExprBindingMap, ExprBindingMapEntry, ExprCall, ExprConditional, ExprDescent,
+    ExprIndex, ExprInteger, ExprList, ExprMap, ExprMapEntry, ExprMatch, ExprOpBinary, ExprOpUnary, ExprPipe, ExprQuery,
+    ExprReal, ExprSlice, ExprString, ExprWildcard, RegexCache, Statement, StatementCall, StatementDefine, StatementFor,
+    StatementIf, StatementInclude, StatementLet, StatementMatch, StatementWith,
 };
 
 use super::lexer::{gen_lexer, Token};
@@ -34,10 +36,7 @@ impl TemplateExprParser {
     pub fn parse(&self, expr_str: &str) -> Result<(Statement, usize), Error> {
         let tokens_res = self.lexer.parse(expr_str);
         if let Err(errs) = tokens_res {
-            for err in &errs {
-                println!("Parse error: {}", err)
-            }
-            return Err(anyhow!("expression parse errors (count={})", errs.len()));
+            return Err(report_parse_errors(&errs));
         }
 
         let expr_str_len = expr_str.chars().count();
@@ -46,16 +45,33 @@ impl TemplateExprParser {
 
         let res = self.parser.parse(Stream::from_iter(eoi, tokens.into_iter()));
         if let Err(errs) = res {
-            for err in &errs {
-                println!("Parse error: {}", err)
-            }
-            return Err(anyhow!("expression parse errors (count={})", errs.len()));
+            return Err(report_parse_errors(&errs));
         }
         let (statement, span) = res.unwrap();
         Ok((statement, span.end()))
     }
 }
 
+// Prints the parse errors and returns the error to report to the user.
+// A custom error describes the problem better than a generic parse error. So, it is returned as-is.
+fn report_parse_errors<T: Hash + Eq + Display>(errs: &[Simple<T>]) -> Error {
+    let mut custom_msg = None;
+    for err in errs {
+        match err.reason() {
+            SimpleReason::Custom(msg) => {
+                println!("Parse error: {}", msg);
+                custom_msg.get_or_insert(msg);
+            }
+            _ => println!("Parse error: {}", err),
+        }
+    }
+
+    match custom_msg {
+        Some(msg) => anyhow!("{}", msg),
+        None => anyhow!("expression parse errors (count={})", errs.len()),
+    }
+}
+
 fn gen_template_expression_parser() -> impl Parser<Token, (Statement, Range<usize>), Error = Simple<Token>> {
     let var = select! {Token::Variable(name) => name}.labelled("variable");
 
@@ -68,6 +84,7 @@ fn gen_template_expression_parser() -> impl Parser<Token, (Statement, Range<usiz
             Token::Ident(ident) if ident == "drop" => Expr::Drop,
             Token::Ident(ident) if ident == "true" => Expr::True,
             Token::Ident(ident) if ident == "false" => Expr::False,
+            Token::Ident(ident) if ident == "null" => Expr::Null,
         }
         .labelled("value");
 
@@ -78,79 +95,453 @@ fn gen_template_expression_parser() -> impl Parser<Token, (Statement, Range<usiz
         let query_var = var.map(|name| ExprQuery::Var(Rc::new(name)));
 
         enum SubQuery {
-            Index(Expr),
+            Index(Expr, bool),
+            Slice(Option<Expr>, Option<Expr>, bool),
+            Wildcard(bool),
+            Descent(Expr),
         }
 
-        let subquery_ident = ident.map(|index| SubQuery::Index(Expr::String(ExprString { value: Rc::new(index) })));
+        // A `?` suffix makes the segment optional.
+        let optional = just(Token::Question).or_not().map(|question| question.is_some());
+
+        let ident_index = ident.map(|index| Expr::String(ExprString { value: Rc::new(index) }));
 
-        let subquery_index = expr
+        let subquery_ident = ident_index
+            .then(optional.clone())
+            .map(|(index, optional)| SubQuery::Index(index, optional));
+
+        let subquery_wildcard = just(Token::LBracket)
+            .then(just(Token::Star))
+            .then(just(Token::RBracket))
+            .ignore_then(optional.clone())
+            .map(SubQuery::Wildcard);
+
+        // `[start:end]`, where both the start and the end are optional.
+        let subquery_slice = expr
+            .clone()
+            .or_not()
+            .then_ignore(just(Token::Colon))
+            .then(expr.clone().or_not())
             .delimited_by(just(Token::LBracket), just(Token::RBracket))
-            .map(|index| SubQuery::Index(index));
+            .then(optional.clone())
+            .map(|((start, end), optional)| SubQuery::Slice(start, end, optional));
+
+        let bracket_index = expr.clone().delimited_by(just(Token::LBracket), just(Token::RBracket));
+
+        let subquery_index = bracket_index
+            .clone()
+            .then(optional)
+            .map(|(index, optional)| SubQuery::Index(index, optional));
+
+        let subquery_brackets = subquery_wildcard.or(subquery_slice).or(subquery_index);
+
+        let subquery = subquery_ident.or(subquery_brackets.clone());
 
-        let subquery = subquery_ident.or(subquery_index);
+        // Recursive descent (e.g. `..name`).
+        let subquery_descent = just(Token::Dot)
+            .then(just(Token::Dot))
+            .ignore_then(ident_index.or(bracket_index))
+            .map(SubQuery::Descent);
 
         let subquery_fold = move |object, subquery| match subquery {
-            SubQuery::Index(index) => ExprQuery::Index(ExprIndex {
+            SubQuery::Index(index, optional) => ExprQuery::Index(ExprIndex {
+                object: Box::new(object),
+                index: Box::new(index),
+                optional,
+            }),
+            SubQuery::Slice(start, end, optional) => ExprQuery::Slice(ExprSlice {
+                object: Box::new(object),
+                start: start.map(Box::new),
+                end: end.map(Box::new),
+                optional,
+            }),
+            SubQuery::Wildcard(optional) => ExprQuery::Wildcard(ExprWildcard {
+                object: Box::new(object),
+                optional,
+            }),
+            SubQuery::Descent(index) => ExprQuery::Descent(ExprDescent {
                 object: Box::new(object),
                 index: Box::new(index),
             }),
         };
 
+        let query_root_descent = subquery_descent
+            .clone()
+            .map(move |subquery| subquery_fold(ExprQuery::Root, subquery));
+
         let query_root_child = query_root
             .clone()
             .then(subquery.clone())
             .map(move |(object, index)| subquery_fold(object, index));
 
-        let query = query_root_child
+        // After the first segment, the period before a bracketed segment may be omitted (e.g. `.items[0]`).
+        let query = query_root_descent
+            .or(query_root_child)
             .or(query_var)
-            .then(just(Token::Dot).ignore_then(subquery).repeated())
+            .then(
+                subquery_descent
+                    .or(just(Token::Dot).ignore_then(subquery))
+                    .or(subquery_brackets)
+                    .repeated(),
+            )
             .foldl(subquery_fold);
 
         let query = query.or(query_root).map(|query| Expr::Query(query));
 
-        let atom = value.or(query);
+        let call = ident
+            .then(
+                expr.clone()
+                    .separated_by(just(Token::Comma))
+                    .delimited_by(just(Token::LParen), just(Token::RParen)),
+            )
+            .map(|(name, args)| {
+                Expr::Call(ExprCall {
+                    name: Rc::new(name),
+                    args,
+                    regex_cache: RegexCache::default(),
+                })
+            });
+
+        let parens = expr.clone().delimited_by(just(Token::LParen), just(Token::RParen));
+
+        let list = expr
+            .clone()
+            .separated_by(just(Token::Comma))
+            .allow_trailing()
+            .delimited_by(just(Token::LBracket), just(Token::RBracket))
+            .map(|items| Expr::List(ExprList { items }));
+
+        let map_entry = expr
+            .clone()
+            .then_ignore(just(Token::Colon))
+            .then(expr.clone())
+            .map(|(key, value)| ExprMapEntry { key, value });
+
+        let map = map_entry
+            .separated_by(just(Token::Comma))
+            .allow_trailing()
+            .delimited_by(just(Token::LBrace), just(Token::RBrace))
+            .map(|entries| Expr::Map(ExprMap { entries }));
+
+        // Some of the precedence levels are boxed to limit the depth of the nested parser types. Otherwise, parsing
+        // can overflow the stack in debug builds.
+        let atom = call.or(value).or(query).or(parens).or(list).or(map).boxed();
+
+        let neg = just(Token::Minus).repeated().then(atom).foldr(|_, value| match value {
+            // Fold negative number literals.
+            Expr::Integer(ExprInteger { value }) if value != i64::MIN => Expr::Integer(ExprInteger { value: -value }),
+            Expr::Real(ExprReal { value }) => {
+                let value = match value.strip_prefix('-') {
+                    Some(value) => value.to_string(),
+                    None => format!("-{}", value),
+                };
+                Expr::Real(ExprReal { value: Rc::new(value) })
+            }
+            value => Expr::Neg(ExprOpUnary { value: Box::new(value) }),
+        });
 
-        let compare_op = just(Token::Eq).or(just(Token::Ne));
+        let product_op = just(Token::Star).or(just(Token::Slash)).or(just(Token::Percent));
 
-        let compare = atom
+        let product = neg
             .clone()
-            .then(compare_op.then(atom).repeated())
-            .foldl(|left, (token, right)| match token {
-                Token::Eq => Expr::Eq(ExprOpBinary {
+            .then(product_op.then(neg).repeated())
+            .foldl(|left, (token, right)| {
+                let op = ExprOpBinary {
                     left: Box::new(left),
                     right: Box::new(right),
-                }),
-                Token::Ne => Expr::Ne(ExprOpBinary {
+                };
+                match token {
+                    Token::Star => Expr::Mul(op),
+                    Token::Slash => Expr::Div(op),
+                    Token::Percent => Expr::Rem(op),
+                    _ => unreachable!(),
+                }
+            });
+
+        let sum_op = just(Token::Plus).or(just(Token::Minus));
+
+        let sum = product
+            .clone()
+            .then(sum_op.then(product).repeated())
+            .foldl(|left, (token, right)| {
+                let op = ExprOpBinary {
                     left: Box::new(left),
                     right: Box::new(right),
-                }),
-                _ => unreachable!(),
+                };
+                match token {
+                    Token::Plus => Expr::Add(op),
+                    Token::Minus => Expr::Sub(op),
+                    _ => unreachable!(),
+                }
+            })
+            .boxed();
+
+        // Null-coalescing is right associative, so that `a ?? b ?? c` tolerates missing paths in both `a` and `b`.
+        let coalesce = sum
+            .clone()
+            .then_ignore(just(Token::Coalesce))
+            .repeated()
+            .then(sum)
+            .foldr(|left, right| {
+                Expr::Coalesce(ExprOpBinary {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                })
+            });
+
+        // A filter is a function call where the piped value is passed as the first argument.
+        // The parentheses may be omitted if there are no other arguments.
+        let filter = ident
+            .then(
+                expr.clone()
+                    .separated_by(just(Token::Comma))
+                    .delimited_by(just(Token::LParen), just(Token::RParen))
+                    .or_not(),
+            )
+            .map(|(name, args)| ExprCall {
+                name: Rc::new(name),
+                args: args.unwrap_or_default(),
+                regex_cache: RegexCache::default(),
+            });
+
+        let pipe = coalesce
+            .clone()
+            .then(just(Token::Pipe).ignore_then(filter).repeated())
+            .foldl(|value, filter| {
+                Expr::Pipe(ExprPipe {
+                    value: Box::new(value),
+                    filter,
+                })
+            });
+
+        #[derive(Clone)]
+        enum CompareOp {
+            Eq,
+            Ne,
+            Lt,
+            Le,
+            Gt,
+            Ge,
+            In,
+            NotIn,
+            Match,
+        }
+
+        let compare_op = just(Token::Eq)
+            .to(CompareOp::Eq)
+            .or(just(Token::Ne).to(CompareOp::Ne))
+            .or(just(Token::Lt).to(CompareOp::Lt))
+            .or(just(Token::Le).to(CompareOp::Le))
+            .or(just(Token::Gt).to(CompareOp::Gt))
+            .or(just(Token::Ge).to(CompareOp::Ge))
+            .or(just(Token::Match).to(CompareOp::Match))
+            .or(just(Token::Ident("in".to_string())).to(CompareOp::In))
+            .or(just(Token::Ident("not".to_string()))
+                .then(just(Token::Ident("in".to_string())))
+                .to(CompareOp::NotIn));
+
+        // Comparison operators are non-associative. So, reject chains like `a == b == c`.
+        let chained_compare = compare_op
+            .clone()
+            .then(pipe.clone())
+            .map_with_span(|_, span| span)
+            .repeated()
+            .validate(|chained_spans: Vec<Range<usize>>, _, emit| {
+                if let Some(span) = chained_spans.first() {
+                    emit(Simple::custom(
+                        span.clone(),
+                        "comparison operators cannot be chained (use parentheses instead)",
+                    ))
+                }
             });
 
-        compare
+        let compare = pipe
+            .clone()
+            .then(compare_op.then(pipe).or_not())
+            .then_ignore(chained_compare)
+            .map(|(left, right)| {
+                let Some((compare_op, right)) = right else {
+                    return left;
+                };
+                let op = ExprOpBinary {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                };
+                match compare_op {
+                    CompareOp::Eq => Expr::Eq(op),
+                    CompareOp::Ne => Expr::Ne(op),
+                    CompareOp::Lt => Expr::Lt(op),
+                    CompareOp::Le => Expr::Le(op),
+                    CompareOp::Gt => Expr::Gt(op),
+                    CompareOp::Ge => Expr::Ge(op),
+                    CompareOp::In => Expr::In(op),
+                    CompareOp::NotIn => Expr::NotIn(op),
+                    CompareOp::Match => Expr::Match(ExprMatch {
+                        value: op.left,
+                        pattern: op.right,
+                        regex_cache: RegexCache::default(),
+                    }),
+                }
+            })
+            .boxed();
+
+        let not = just(Token::Ident("not".to_string()))
+            .repeated()
+            .then(compare)
+            .foldr(|_, value| Expr::Not(ExprOpUnary { value: Box::new(value) }));
+
+        let and = not
+            .clone()
+            .then(just(Token::Ident("and".to_string())).ignore_then(not).repeated())
+            .foldl(|left, right| {
+                Expr::And(ExprOpBinary {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                })
+            });
+
+        let or = and
+            .clone()
+            .then(just(Token::Ident("or".to_string())).ignore_then(and).repeated())
+            .foldl(|left, right| {
+                Expr::Or(ExprOpBinary {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                })
+            })
+            .boxed();
+
+        // The conditional expression (`a if cond else b`) is right associative, so that they can be chained like
+        // `a if cond1 else b if cond2 else c`.
+        or.clone()
+            .then(
+                just(Token::Ident("if".to_string()))
+                    .ignore_then(or)
+                    .then_ignore(just(Token::Ident("else".to_string())))
+                    .then(expr)
+                    .or_not(),
+            )
+            .map(|(true_value, branches)| match branches {
+                Some((condition, false_value)) => Expr::Conditional(ExprConditional {
+                    condition: Box::new(condition),
+                    true_value: Box::new(true_value),
+                    false_value: Box::new(false_value),
+                }),
+                None => true_value,
+            })
     });
 
-    let var_binding = var.map(|name| ExprBinding::Var(Rc::new(name)));
+    let binding = recursive(|binding| {
+        let var_binding = var.map(|name| ExprBinding::Var(Rc::new(name)));
+
+        // `{name: $name, "port": $port}`
+        let map_binding_key =
+            name_token(|token| matches!(token, Token::Ident(_) | Token::String(_))).labelled("field name");
+
+        let map_binding_entry = map_binding_key
+            .then_ignore(just(Token::Colon))
+            .then(binding.clone())
+            .map(|(key, binding)| ExprBindingMapEntry { key, binding });
+
+        let map_binding = map_binding_entry
+            .separated_by(just(Token::Comma))
+            .allow_trailing()
+            .at_least(1)
+            .delimited_by(just(Token::LBrace), just(Token::RBrace))
+            .map(|entries| ExprBinding::Map(ExprBindingMap { entries }));
+
+        // `[$first, $second]`
+        let list_binding = binding
+            .separated_by(just(Token::Comma))
+            .allow_trailing()
+            .at_least(1)
+            .delimited_by(just(Token::LBracket), just(Token::RBracket))
+            .map(|items| ExprBinding::List(ExprBindingList { items }));
 
-    let binding = var_binding;
+        var_binding.or(map_binding).or(list_binding)
+    });
 
     let bindings = binding
         .clone()
-        .chain(just(Token::Comma).ignore_then(binding).repeated());
+        .chain(just(Token::Comma).ignore_then(binding.clone()).repeated());
 
     let if_statment = just(Token::Ident("if".to_string()))
         .ignore_then(expr.clone())
         .map(|condition| Statement::If(StatementIf { condition }));
 
+    let elif_statment = just(Token::Ident("elif".to_string()))
+        .ignore_then(expr.clone())
+        .map(|condition| Statement::Elif(StatementIf { condition }));
+
+    let else_statment = just(Token::Ident("else".to_string())).to(Statement::Else);
+
     let for_statement = just(Token::Ident("for".to_string()))
         .ignore_then(bindings)
         .then_ignore(just(Token::Ident("in".to_string())))
         .then(expr.clone())
-        .map(|(bindings, iterable)| Statement::For(StatementFor { bindings, iterable }));
+        .then(just(Token::Ident("if".to_string())).ignore_then(expr.clone()).or_not())
+        .map(|((bindings, iterable), filter)| {
+            Statement::For(StatementFor {
+                bindings,
+                iterable,
+                filter,
+            })
+        });
+
+    let let_statement = just(Token::Ident("let".to_string()))
+        .ignore_then(binding.clone())
+        .then_ignore(just(Token::Assign))
+        .then(expr.clone())
+        .map(|(binding, value)| Statement::Let(StatementLet { binding, value }));
+
+    let with_statement = just(Token::Ident("with".to_string()))
+        .ignore_then(expr.clone())
+        .map(|value| Statement::With(StatementWith { value }));
+
+    let match_statement = just(Token::Ident("match".to_string()))
+        .ignore_then(expr.clone())
+        .map(|value| Statement::Match(StatementMatch { value }));
+
+    let macro_name = name_token(|token| matches!(token, Token::String(_))).labelled("macro name");
+
+    let define_statement = just(Token::Ident("define".to_string()))
+        .ignore_then(macro_name.clone())
+        .then(
+            binding
+                .clone()
+                .separated_by(just(Token::Comma))
+                .allow_trailing()
+                .delimited_by(just(Token::LParen), just(Token::RParen)),
+        )
+        .map(|(name, params)| Statement::Define(StatementDefine { name, params }));
+
+    let call_statement = just(Token::Ident("call".to_string()))
+        .ignore_then(macro_name)
+        .then(
+            expr.clone()
+                .separated_by(just(Token::Comma))
+                .allow_trailing()
+                .delimited_by(just(Token::LParen), just(Token::RParen)),
+        )
+        .map(|(name, args)| Statement::Call(StatementCall { name, args }));
+
+    let include_statement = just(Token::Ident("include".to_string()))
+        .ignore_then(expr.clone())
+        .map(|path| Statement::Include(StatementInclude { path }));
 
     let expr_statement = expr.map(|expr| Statement::Expr(expr));
 
-    let statement = if_statment.or(for_statement).or(expr_statement);
+    let statement = if_statment
+        .or(elif_statment)
+        .or(else_statment)
+        .or(for_statement)
+        .or(let_statement)
+        .or(with_statement)
+        .or(match_statement)
+        .or(define_statement)
+        .or(call_statement)
+        .or(include_statement)
+        .or(expr_statement);
 
     let templ_expr = just(Token::Start)
         .ignore_then(statement)
@@ -159,3 +550,12 @@ fn gen_template_expression_parser() -> impl Parser<Token, (Statement, Range<usiz
 
     templ_expr
 }
+
+// Matches an identifier or string token that `accept` allows, and returns its text.
+// Uses `filter` instead of `select!`, so that the large parse error isn't returned from a closure.
+fn name_token(accept: fn(&Token) -> bool) -> impl Parser<Token, Rc<String>, Error = Simple<Token>> + Clone {
+    filter(accept).map(|token| match token {
+        Token::Ident(name) | Token::String(name) => Rc::new(name),
+        _ => unreachable!(),
+    })
+}
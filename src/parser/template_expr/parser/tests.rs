@@ -17,15 +17,74 @@ macro_rules! testlist {
 }
 
 testlist! {
+    arithmetic_integer_out_of_range,
+    arithmetic_negative,
+    arithmetic_parens,
+    arithmetic_precedence,
+    call_no_args,
+    call_nested,
+    call_statement,
+    coalesce_chain,
+    coalesce_precedence,
+    compare_chained,
+    compare_chained_parens,
+    conditional_chain,
+    conditional_missing_else,
+    conditional_precedence,
+    conditional_simple,
+    define_no_params,
+    define_simple,
     drop_simple,
     drop_with_whitespace,
+    elif_simple,
+    else_simple,
+    for_destructure_list,
+    for_destructure_map,
+    for_destructure_nested,
+    for_filter,
+    for_filter_conditional_iterable,
+    in_chained,
+    in_for_iterable,
+    in_not,
+    in_simple,
+    include_simple,
     inline_simple,
     inline_with_whitespace,
+    let_compare,
+    let_destructure,
+    let_missing_value,
+    let_simple,
+    list_empty,
+    list_simple,
+    logical_precedence,
+    map_empty,
+    map_nested,
+    map_nested_no_space,
+    match_simple,
+    match_statement,
+    not_double,
+    order_compare,
+    parens_nested,
+    parens_not,
+    parens_unclosed,
+    pipe_chain,
+    pipe_precedence,
     query_child,
+    query_descent_nested,
+    query_descent_root,
+    query_index_no_dot,
     query_nested_child,
+    query_optional,
+    query_optional_coalesce,
     query_root,
+    query_root_slice,
+    query_slice,
+    query_wildcard,
+    query_wildcard_optional,
     string_simple,
     string_with_whitespace,
+    with_coalesce,
+    with_simple,
 }
 
 fn run_test(name: &str) {
@@ -74,16 +133,80 @@ fn fomat_statement(string: &mut String, statement: &Statement) {
     match statement {
         Statement::Expr(expr) => fomat_expr(string, expr),
         Statement::If(statement) => fomat_if(string, statement),
+        Statement::Elif(statement) => fomat_elif(string, statement),
+        Statement::Else => string.push_str("else"),
         Statement::For(statement) => fomat_for(string, statement),
+        Statement::Let(statement) => fomat_let(string, statement),
+        Statement::With(statement) => fomat_with(string, statement),
+        Statement::Match(statement) => fomat_match_statement(string, statement),
+        Statement::Define(statement) => fomat_define(string, statement),
+        Statement::Call(statement) => fomat_call_statement(string, statement),
+        Statement::Include(statement) => fomat_include(string, statement),
     }
 }
 
+fn fomat_include(string: &mut String, statement: &StatementInclude) {
+    string.push_str("include (");
+    fomat_expr(string, &statement.path);
+    string.push(')');
+}
+
+fn fomat_define(string: &mut String, statement: &StatementDefine) {
+    string.push_str(&format!("define {:?}(", statement.name.as_str()));
+    for (i, param) in statement.params.iter().enumerate() {
+        if i > 0 {
+            string.push_str(", ");
+        }
+        fomat_binding(string, param);
+    }
+    string.push(')');
+}
+
+fn fomat_call_statement(string: &mut String, statement: &StatementCall) {
+    string.push_str(&format!("call {:?}(", statement.name.as_str()));
+    for (i, arg) in statement.args.iter().enumerate() {
+        if i > 0 {
+            string.push_str(", ");
+        }
+        string.push('(');
+        fomat_expr(string, arg);
+        string.push(')');
+    }
+    string.push(')');
+}
+
+fn fomat_match_statement(string: &mut String, statement: &StatementMatch) {
+    string.push_str("match (");
+    fomat_expr(string, &statement.value);
+    string.push(')');
+}
+
+fn fomat_with(string: &mut String, statement: &StatementWith) {
+    string.push_str("with (");
+    fomat_expr(string, &statement.value);
+    string.push(')');
+}
+
+fn fomat_let(string: &mut String, statement: &StatementLet) {
+    string.push_str("let (");
+    fomat_binding(string, &statement.binding);
+    string.push_str(") = (");
+    fomat_expr(string, &statement.value);
+    string.push(')');
+}
+
 fn fomat_if(string: &mut String, statement: &StatementIf) {
     string.push_str("if (");
     fomat_expr(string, &statement.condition);
     string.push_str("if )");
 }
 
+fn fomat_elif(string: &mut String, statement: &StatementIf) {
+    string.push_str("elif (");
+    fomat_expr(string, &statement.condition);
+    string.push_str("elif )");
+}
+
 fn fomat_for(string: &mut String, statement: &StatementFor) {
     string.push_str("for ");
     for (i, binding) in statement.bindings.iter().enumerate() {
@@ -98,11 +221,38 @@ fn fomat_for(string: &mut String, statement: &StatementFor) {
     string.push_str(" in (");
     fomat_expr(string, &statement.iterable);
     string.push_str(")");
+
+    if let Some(filter) = &statement.filter {
+        string.push_str(" if (");
+        fomat_expr(string, filter);
+        string.push(')');
+    }
 }
 
 fn fomat_binding(string: &mut String, binding: &ExprBinding) {
     match binding {
         ExprBinding::Var(name) => string.push_str(&format!("${}", name.as_ref().as_str())),
+        ExprBinding::Map(map) => {
+            string.push('{');
+            for (i, entry) in map.entries.iter().enumerate() {
+                if i > 0 {
+                    string.push_str(", ");
+                }
+                string.push_str(&format!("{:?}: ", entry.key.as_str()));
+                fomat_binding(string, &entry.binding);
+            }
+            string.push('}');
+        }
+        ExprBinding::List(list) => {
+            string.push('[');
+            for (i, item) in list.items.iter().enumerate() {
+                if i > 0 {
+                    string.push_str(", ");
+                }
+                fomat_binding(string, item);
+            }
+            string.push(']');
+        }
     }
 }
 
@@ -116,21 +266,146 @@ fn fomat_expr(string: &mut String, expr: &Expr) {
         Expr::False => string.push_str("false"),
         Expr::Eq(op) => fomat_binary_op(string, "==", op),
         Expr::Ne(op) => fomat_binary_op(string, "!=", op),
+        Expr::Lt(op) => fomat_binary_op(string, "<", op),
+        Expr::Le(op) => fomat_binary_op(string, "<=", op),
+        Expr::Gt(op) => fomat_binary_op(string, ">", op),
+        Expr::Ge(op) => fomat_binary_op(string, ">=", op),
         Expr::Integer(value) => string.push_str(&format!("{}", value.value)),
         Expr::Real(value) => string.push_str(&value.value),
+        Expr::And(op) => fomat_binary_op(string, "and", op),
+        Expr::Or(op) => fomat_binary_op(string, "or", op),
+        Expr::Not(op) => fomat_unary_op(string, "not", op),
+        Expr::Add(op) => fomat_binary_op(string, "+", op),
+        Expr::Sub(op) => fomat_binary_op(string, "-", op),
+        Expr::Mul(op) => fomat_binary_op(string, "*", op),
+        Expr::Div(op) => fomat_binary_op(string, "/", op),
+        Expr::Rem(op) => fomat_binary_op(string, "%", op),
+        Expr::Neg(op) => fomat_unary_op(string, "-", op),
+        Expr::Call(call) => fomat_call(string, call),
+        Expr::Pipe(pipe) => fomat_pipe(string, pipe),
+        Expr::List(list) => fomat_list(string, list),
+        Expr::Map(map) => fomat_map(string, map),
+        Expr::Null => string.push_str("null"),
+        Expr::Coalesce(op) => fomat_binary_op(string, "??", op),
+        Expr::Conditional(conditional) => fomat_conditional(string, conditional),
+        Expr::In(op) => fomat_binary_op(string, "in", op),
+        Expr::NotIn(op) => fomat_binary_op(string, "not in", op),
+        Expr::Match(expr_match) => {
+            string.push('(');
+            fomat_expr(string, &expr_match.value);
+            string.push_str(")=~(");
+            fomat_expr(string, &expr_match.pattern);
+            string.push(')');
+        }
+    }
+}
+
+fn fomat_list(string: &mut String, list: &ExprList) {
+    string.push('[');
+    for (i, item) in list.items.iter().enumerate() {
+        if i > 0 {
+            string.push_str(", ");
+        }
+        fomat_expr(string, item);
+    }
+    string.push(']');
+}
+
+fn fomat_map(string: &mut String, map: &ExprMap) {
+    string.push('{');
+    for (i, entry) in map.entries.iter().enumerate() {
+        if i > 0 {
+            string.push_str(", ");
+        }
+        fomat_expr(string, &entry.key);
+        string.push_str(": ");
+        fomat_expr(string, &entry.value);
+    }
+    string.push('}');
+}
+
+fn fomat_conditional(string: &mut String, conditional: &ExprConditional) {
+    string.push('(');
+    fomat_expr(string, &conditional.true_value);
+    string.push_str(")if(");
+    fomat_expr(string, &conditional.condition);
+    string.push_str(")else(");
+    fomat_expr(string, &conditional.false_value);
+    string.push(')');
+}
+
+fn fomat_pipe(string: &mut String, pipe: &ExprPipe) {
+    string.push('(');
+    fomat_expr(string, &pipe.value);
+    string.push_str(")|");
+    fomat_call(string, &pipe.filter);
+}
+
+fn fomat_call(string: &mut String, call: &ExprCall) {
+    string.push_str(&call.name);
+    string.push('(');
+    for (i, arg) in call.args.iter().enumerate() {
+        if i > 0 {
+            string.push_str(", ");
+        }
+        fomat_expr(string, arg);
     }
+    string.push(')');
 }
 
 fn fomat_expr_query(string: &mut String, query: &ExprQuery) {
     match query {
         ExprQuery::Root => string.push_str("."),
-        ExprQuery::Index(ExprIndex { object, index }) => {
+        ExprQuery::Index(ExprIndex {
+            object,
+            index,
+            optional,
+        }) => {
             string.push_str("(");
             fomat_expr_query(string, object);
             string.push_str(").");
             string.push_str("[");
             fomat_expr(string, index);
             string.push_str("]");
+            if *optional {
+                string.push('?');
+            }
+        }
+        ExprQuery::Slice(ExprSlice {
+            object,
+            start,
+            end,
+            optional,
+        }) => {
+            string.push('(');
+            fomat_expr_query(string, object);
+            string.push_str(").[");
+            if let Some(start) = start {
+                fomat_expr(string, start);
+            }
+            string.push(':');
+            if let Some(end) = end {
+                fomat_expr(string, end);
+            }
+            string.push(']');
+            if *optional {
+                string.push('?');
+            }
+        }
+        ExprQuery::Wildcard(ExprWildcard { object, optional }) => {
+            string.push('(');
+            fomat_expr_query(string, object);
+            string.push_str(").[*]");
+            if *optional {
+                string.push('?');
+            }
+        }
+        ExprQuery::Descent(ExprDescent { object, index }) => {
+            string.push('(');
+            fomat_expr_query(string, object);
+            string.push_str(")..[");
+            fomat_expr(string, index);
+            string.push(']');
         }
         ExprQuery::Var(name) => string.push_str(&format!("${}", name.as_ref().as_str())),
     }
@@ -145,3 +420,10 @@ fn fomat_binary_op(string: &mut String, op: &str, binary_op: &ExprOpBinary) {
     fomat_expr(string, &binary_op.right);
     string.push_str(")");
 }
+
+fn fomat_unary_op(string: &mut String, op: &str, unary_op: &ExprOpUnary) {
+    string.push_str(op);
+    string.push('(');
+    fomat_expr(string, &unary_op.value);
+    string.push(')');
+}
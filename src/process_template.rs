@@ -48,7 +48,7 @@ fn process_yaml_template(
         _ => return Err(anyhow!("config yaml must only have a single document")),
     };
 
-    let file = interpret(&template, config, variables)?;
+    let file = interpret(&parser, &template, config, variables)?;
     Ok(file)
 }
 
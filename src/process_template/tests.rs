@@ -57,6 +57,7 @@ testlist! {
     inline_value_into_map,
     simple_string,
     simple_string_expr,
+    non_ascii_expression,
     true_value,
     false_value,
     true_eq_true,
@@ -68,6 +69,130 @@ testlist! {
     string_eq_itself,
     string_eq_config_var,
     if_config_var,
+    if_else_map,
+    if_elif_else_list,
+    if_else_chain_restart,
+    else_orphan,
+    elif_orphan_list,
+    else_orphan_value,
+    and_or_not,
+    or_default_value,
+    and_short_circuit,
+    not_inline,
+    compare_numbers,
+    compare_numbers_equality,
+    compare_strings,
+    compare_mismatched_types,
+    arithmetic_integers,
+    arithmetic_reals,
+    arithmetic_overflow,
+    arithmetic_integer_out_of_range,
+    arithmetic_division_by_zero,
+    arithmetic_wrong_type,
+    compare_chained,
+    compare_chained_ordering,
+    compare_chained_parens,
+    function_strings,
+    function_collections,
+    function_unknown,
+    function_wrong_arg_count,
+    function_wrong_type,
+    pipe_filters,
+    pipe_in_statements,
+    pipe_wrong_arg_count,
+    list_literal,
+    map_literal,
+    for_loop_list_literal,
+    list_literal_inline,
+    null_literal,
+    coalesce_missing,
+    coalesce_unknown_variable,
+    coalesce_precedence,
+    coalesce_wrong_type,
+    query_optional,
+    query_optional_required_segment,
+    conditional_expr,
+    conditional_expr_condition_inline,
+    membership,
+    membership_wrong_type,
+    membership_substring_wrong_type,
+    regex_match_operator,
+    regex_functions,
+    regex_in_loop,
+    regex_invalid_pattern,
+    regex_wrong_type,
+    query_index_negative,
+    query_index_negative_out_of_bounds,
+    has_negative_index,
+    query_slice,
+    query_slice_wrong_type,
+    query_slice_wrong_bound_type,
+    query_wildcard,
+    query_wildcard_missing,
+    query_wildcard_wrong_type,
+    query_descent,
+    let_simple,
+    let_with_value,
+    let_quoted_tilde_value,
+    let_scope,
+    let_shadowing,
+    let_in_list,
+    let_as_value,
+    with_simple,
+    with_null_drops,
+    with_nested,
+    with_wrong_usage,
+    match_simple,
+    match_default,
+    match_no_case,
+    match_case_not_literal,
+    match_scalar_types,
+    match_integer_no_case,
+    loop_metadata,
+    loop_metadata_map,
+    loop_metadata_nested,
+    for_filter,
+    for_filter_map,
+    for_filter_null,
+    range_loop,
+    range_negative_step,
+    range_value,
+    range_limit,
+    range_zero_step,
+    range_wrong_type,
+    range_wrong_arg_count,
+    zip_loop,
+    product_loop,
+    tuple_bindings_mismatch,
+    tuple_bindings_not_list,
+    zip_wrong_type,
+    product_wrong_arg_count,
+    destructure_map,
+    destructure_list,
+    destructure_nested,
+    destructure_missing_field,
+    destructure_wrong_type,
+    destructure_list_length,
+    macro_simple,
+    macro_inline_map,
+    macro_recursion,
+    macro_depth_limit,
+    macro_error_in_body,
+    macro_unknown,
+    macro_wrong_arg_count,
+    macro_scope,
+    macro_lexical_scope,
+    macro_caller_variables_hidden,
+    macro_defined_later,
+    include_map,
+    include_list,
+    include_relative,
+    include_scope,
+    include_cycle,
+    include_error,
+    include_missing_file,
+    include_multiple_documents,
+    include_wrong_type,
     arg_var_simple,
     arg_var_missing,
     arg_var_if,